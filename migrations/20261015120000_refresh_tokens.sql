@@ -0,0 +1,10 @@
+CREATE TABLE refresh_tokens (
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash BYTEA NOT NULL UNIQUE
+);
+
+ALTER TABLE refresh_tokens
+ENABLE ROW LEVEL SECURITY;
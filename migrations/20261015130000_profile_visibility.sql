@@ -0,0 +1,8 @@
+DO $$ BEGIN
+  CREATE TYPE profile_visibility AS ENUM ('public', 'unlisted', 'private');
+EXCEPTION
+  WHEN duplicate_object THEN null;
+END $$;
+
+ALTER TABLE profiles
+ADD COLUMN visibility profile_visibility NOT NULL DEFAULT 'public';
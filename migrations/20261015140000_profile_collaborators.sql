@@ -0,0 +1,9 @@
+CREATE TABLE profile_collaborators (
+    profile_id UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (profile_id, user_id)
+);
+
+ALTER TABLE profile_collaborators
+ENABLE ROW LEVEL SECURITY;
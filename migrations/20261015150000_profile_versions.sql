@@ -0,0 +1,14 @@
+CREATE TABLE profile_versions (
+    profile_id UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    version INT NOT NULL,
+    storage_key TEXT NOT NULL,
+    name TEXT NOT NULL,
+    community TEXT,
+    mods JSONB NOT NULL,
+    code UUID,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (profile_id, version)
+);
+
+ALTER TABLE profile_versions
+ENABLE ROW LEVEL SECURITY;
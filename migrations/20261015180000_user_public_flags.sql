@@ -0,0 +1,2 @@
+ALTER TABLE users
+ADD COLUMN public_flags INT NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+ALTER TABLE users
+ADD COLUMN banner TEXT,
+ADD COLUMN avatar_decoration TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE users
+ADD COLUMN public_id UUID NOT NULL UNIQUE DEFAULT gen_random_uuid();
@@ -0,0 +1,2 @@
+ALTER TABLE profiles
+ADD COLUMN updated_by INT REFERENCES users(id) ON DELETE SET NULL;
@@ -0,0 +1,2 @@
+ALTER TABLE profiles
+ADD COLUMN size_bytes BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,13 @@
+CREATE TABLE moderation_log (
+    id BIGSERIAL PRIMARY KEY,
+    admin_id INT REFERENCES users(id) ON DELETE SET NULL,
+    action TEXT NOT NULL,
+    -- profiles are deleted by some actions, so they aren't referenced by key
+    profile_id VARCHAR(22) NOT NULL,
+    owner_id INT REFERENCES users(id) ON DELETE SET NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE moderation_log
+ENABLE ROW LEVEL SECURITY;
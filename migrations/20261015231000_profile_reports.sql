@@ -0,0 +1,18 @@
+DO $$ BEGIN
+  CREATE TYPE report_category AS ENUM ('spam', 'inappropriate', 'malicious', 'other');
+EXCEPTION
+  WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE profile_reports (
+    profile_id UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    reporter_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    category report_category NOT NULL,
+    details TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (profile_id, reporter_id)
+);
+
+ALTER TABLE profile_reports
+ENABLE ROW LEVEL SECURITY;
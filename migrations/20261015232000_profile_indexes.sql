@@ -0,0 +1,8 @@
+-- short_id_unique already creates an index
+DROP INDEX idx_profiles_short_id;
+
+CREATE INDEX idx_profiles_owner_id ON profiles (owner_id);
+
+-- only used to find profiles that still need to be migrated
+CREATE INDEX idx_profiles_code_null ON profiles (id)
+WHERE code IS NULL;
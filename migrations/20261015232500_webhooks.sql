@@ -0,0 +1,12 @@
+CREATE TABLE webhooks (
+    user_id INT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    -- consecutive failed deliveries, the webhook is disabled after too many
+    failures INT NOT NULL DEFAULT 0,
+    disabled_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE webhooks
+ENABLE ROW LEVEL SECURITY;
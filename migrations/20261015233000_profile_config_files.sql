@@ -0,0 +1,7 @@
+ALTER TABLE profiles
+ADD COLUMN config_file_count INT NOT NULL DEFAULT 0,
+ADD COLUMN config_size_bytes BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE profile_versions
+ADD COLUMN config_file_count INT NOT NULL DEFAULT 0,
+ADD COLUMN config_size_bytes BIGINT NOT NULL DEFAULT 0;
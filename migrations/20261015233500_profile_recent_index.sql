@@ -0,0 +1,3 @@
+-- used by the recent profiles feed
+CREATE INDEX idx_profiles_public_updated_at ON profiles (updated_at DESC, short_id DESC)
+WHERE visibility = 'public' AND deleted_at IS NULL;
@@ -0,0 +1,10 @@
+-- individual download events, used to rank trending profiles
+CREATE TABLE profile_downloads (
+    profile_id UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    downloaded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX profile_downloads_downloaded_at_idx ON profile_downloads (downloaded_at, profile_id);
+
+ALTER TABLE profile_downloads
+ENABLE ROW LEVEL SECURITY;
@@ -0,0 +1,4 @@
+-- every upload is stored under its own key, this points to the current one.
+-- NULL for profiles last uploaded before that, which live at profile/{short_id}.zip
+ALTER TABLE profiles
+ADD COLUMN storage_key TEXT;
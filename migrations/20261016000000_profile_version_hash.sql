@@ -0,0 +1,13 @@
+ALTER TABLE profile_versions
+ADD COLUMN content_hash BYTEA,
+ADD COLUMN size_bytes BIGINT;
+
+-- older versions can't be backfilled without downloading them, but the current ones can
+UPDATE profile_versions v
+SET
+    content_hash = p.content_hash,
+    size_bytes = p.size_bytes
+FROM profiles p
+WHERE
+    v.profile_id = p.id AND
+    v.storage_key = p.storage_key;
@@ -1,12 +1,20 @@
-use axum::extract::FromRequestParts;
+use axum::extract::{FromRequestParts, OptionalFromRequestParts};
+use http::{header::AUTHORIZATION, HeaderMap};
 use serde::{Deserialize, Serialize};
 
-use crate::prelude::*;
+use uuid::Uuid;
 
+use crate::{prelude::*, short_uuid::ShortUuid};
+
+pub mod refresh;
 pub mod token;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[serde(
+    rename_all = "camelCase",
+    into = "SerializedUser",
+    from = "SerializedUser"
+)]
 pub struct User {
     // don't expose the id
     #[serde(skip)]
@@ -15,6 +23,97 @@ pub struct User {
     pub name: String,
     pub display_name: String,
     pub avatar: Option<String>,
+    /// Discord's badge bitfield, see <https://discord.com/developers/docs/resources/user#user-object-user-flags>.
+    pub public_flags: i32,
+    /// Discord CDN hash of the profile banner.
+    pub banner: Option<String>,
+    /// Discord CDN asset of the avatar decoration.
+    pub avatar_decoration: Option<String>,
+    /// A stable id to refer to the user by, unlike `name` which can change.
+    pub public_id: Uuid,
+    /// Admins can use the admin API, see [`AdminUser`].
+    pub is_admin: bool,
+}
+
+impl User {
+    pub fn avatar_url(&self) -> String {
+        avatar_url(&self.discord_id, self.avatar.as_deref())
+    }
+}
+
+/// How [`User`] is sent to clients, with the avatar resolved to a full URL.
+///
+/// Users are also read back in this form from the metadata cache and pub/sub
+/// messages, which leaves `id` and `is_admin` unset. See [`crate::profile::get`].
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SerializedUser {
+    discord_id: String,
+    name: String,
+    display_name: String,
+    avatar: Option<String>,
+    #[serde(skip_deserializing)]
+    avatar_url: String,
+    public_flags: i32,
+    banner: Option<String>,
+    avatar_decoration: Option<String>,
+    public_id: ShortUuid,
+}
+
+impl From<User> for SerializedUser {
+    fn from(user: User) -> Self {
+        let avatar_url = user.avatar_url();
+
+        SerializedUser {
+            discord_id: user.discord_id,
+            name: user.name,
+            display_name: user.display_name,
+            avatar: user.avatar,
+            avatar_url,
+            public_flags: user.public_flags,
+            banner: user.banner,
+            avatar_decoration: user.avatar_decoration,
+            public_id: user.public_id.into(),
+        }
+    }
+}
+
+impl From<SerializedUser> for User {
+    fn from(user: SerializedUser) -> Self {
+        User {
+            id: 0,
+            discord_id: user.discord_id,
+            name: user.name,
+            display_name: user.display_name,
+            avatar: user.avatar,
+            public_flags: user.public_flags,
+            banner: user.banner,
+            avatar_decoration: user.avatar_decoration,
+            public_id: user.public_id.0,
+            is_admin: false,
+        }
+    }
+}
+
+const DISCORD_CDN_URL: &str = "https://cdn.discordapp.com";
+
+/// Builds the Discord CDN URL of a user's avatar, falling back to their default avatar.
+///
+/// Legacy default avatars are picked by discriminator, which we don't store since
+/// Discord moved everyone to unique usernames. Those users get the new default instead.
+pub fn avatar_url(discord_id: &str, avatar: Option<&str>) -> String {
+    match avatar {
+        Some(hash) if !hash.is_empty() => {
+            let extension = if hash.starts_with("a_") { "gif" } else { "png" };
+
+            format!("{DISCORD_CDN_URL}/avatars/{discord_id}/{hash}.{extension}")
+        }
+        _ => {
+            let index = discord_id.parse::<u64>().map_or(0, |id| (id >> 22) % 6);
+
+            format!("{DISCORD_CDN_URL}/embed/avatars/{index}.png")
+        }
+    }
 }
 
 /// Extractor to verify and extract the user from the provided token.
@@ -27,18 +126,92 @@ impl FromRequestParts<AppState> for AuthUser {
         parts: &mut axum::http::request::Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        let auth = parts
-            .headers
-            .get("Authorization")
-            .and_then(|value| value.to_str().ok())
-            .ok_or_else(|| AppError::unauthorized("Authorization header is missing."))?;
-
-        let token = auth.strip_prefix("Bearer ").ok_or_else(|| {
-            AppError::bad_request("Authorization header must use the Bearer scheme.")
-        })?;
-
+        let token = bearer_token(&parts.headers)?;
         let claims = token::verify(token, state)?;
 
         Ok(AuthUser(claims.user.into()))
     }
 }
+
+/// Only fails if an Authorization header is present but invalid.
+impl OptionalFromRequestParts<AppState> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        if !parts.headers.contains_key(AUTHORIZATION) {
+            return Ok(None);
+        }
+
+        <Self as FromRequestParts<AppState>>::from_request_parts(parts, state)
+            .await
+            .map(Some)
+    }
+}
+
+/// Like [`AuthUser`], but also requires the user to be an admin.
+pub struct AdminUser(pub User);
+
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let AuthUser(user) =
+            <AuthUser as FromRequestParts<AppState>>::from_request_parts(parts, state).await?;
+
+        if !user.is_admin {
+            return Err(AppError::forbidden("Only admins can do this."));
+        }
+
+        Ok(AdminUser(user))
+    }
+}
+
+/// Extracts the token from a `Authorization: Bearer <token>` header.
+pub fn bearer_token(headers: &HeaderMap) -> AppResult<&str> {
+    let auth = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(AppError::MissingAuthorization)?;
+
+    auth.strip_prefix("Bearer ")
+        .ok_or_else(|| AppError::bad_request("Authorization header must use the Bearer scheme."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> User {
+        User {
+            id: 1,
+            discord_id: "123456789012345678".to_owned(),
+            name: "kesomannen".to_owned(),
+            display_name: "Kesomannen".to_owned(),
+            avatar: Some("a_abcdef".to_owned()),
+            public_flags: 64,
+            banner: None,
+            avatar_decoration: None,
+            public_id: Uuid::now_v7(),
+            is_admin: false,
+        }
+    }
+
+    #[test]
+    fn user_json_round_trip() {
+        let user = user();
+
+        let json = serde_json::to_string(&user).unwrap();
+        let parsed: User = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parsed.public_id, user.public_id);
+        assert_eq!(parsed.discord_id, user.discord_id);
+        assert_eq!(parsed.avatar, user.avatar);
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+    }
+}
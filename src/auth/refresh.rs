@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use base64::prelude::*;
+use chrono::Utc;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::PgExecutor;
+
+use crate::prelude::*;
+
+use super::User;
+
+const EXPIRATION_TIME: Duration = Duration::from_secs(30 * 24 * 60 * 60); // 30 days
+
+/// Only the hash of a refresh token is stored, so a database leak doesn't hand out sessions.
+fn hash(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+pub async fn create(user_id: i32, conn: impl PgExecutor<'_>) -> AppResult<String> {
+    let bytes: [u8; 32] = rand::rng().random();
+    let token = BASE64_URL_SAFE_NO_PAD.encode(bytes);
+
+    sqlx::query!(
+        "INSERT INTO refresh_tokens (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)",
+        hash(&token),
+        user_id,
+        Utc::now() + EXPIRATION_TIME
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(token)
+}
+
+/// Consumes a refresh token and issues a new one in its place.
+///
+/// Returns `None` if the token isn't one of ours, for example
+/// if it's a Discord refresh token from before we issued our own.
+pub async fn rotate(token: &str, state: &AppState) -> AppResult<Option<(User, String)>> {
+    let mut tx = state.db.begin().await?;
+
+    let Some(record) = sqlx::query!(
+        "DELETE FROM refresh_tokens
+        WHERE token_hash = $1
+        RETURNING user_id, expires_at",
+        hash(token)
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    if record.expires_at < Utc::now() {
+        tx.commit().await?;
+        return Err(AppError::RefreshTokenExpired);
+    }
+
+    let user = sqlx::query_as!(
+        User,
+        "SELECT id, name, display_name, discord_id, avatar, public_flags, banner, avatar_decoration, public_id, is_admin
+        FROM users
+        WHERE id = $1",
+        record.user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let new_token = create(user.id, &mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(Some((user, new_token)))
+}
@@ -1,16 +1,28 @@
-use std::time::Duration;
+use std::{str::FromStr, time::Duration};
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use base64::prelude::*;
 use chrono::{DateTime, Utc};
 use hmac::{Hmac, Mac};
-use jwt::{SignWithKey, VerifyWithKey};
+use jwt::{AlgorithmType, SignWithKey, SigningAlgorithm, VerifyWithKey, VerifyingAlgorithm};
+use rsa::{
+    pkcs1::DecodeRsaPrivateKey,
+    pkcs1v15::{Signature, SigningKey, VerifyingKey},
+    pkcs8::DecodePrivateKey,
+    signature::{SignatureEncoding, Signer, Verifier},
+    RsaPrivateKey,
+};
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use uuid::Uuid;
 
 use crate::prelude::*;
 
 use super::User;
 
+/// How long access tokens are valid for.
+pub const EXPIRATION_TIME: Duration = Duration::from_secs(30 * 60); // 30 minutes
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JwtClaims {
     #[serde(rename = "exp")]
@@ -29,6 +41,17 @@ pub struct JwtUser {
     name: String,
     display_name: String,
     avatar: Option<String>,
+    // missing from tokens issued before it was added
+    #[serde(default)]
+    public_flags: i32,
+    #[serde(default)]
+    banner: Option<String>,
+    #[serde(default)]
+    avatar_decoration: Option<String>,
+    #[serde(default)]
+    public_id: Uuid,
+    #[serde(default)]
+    is_admin: bool,
 }
 
 impl From<JwtUser> for User {
@@ -39,6 +62,11 @@ impl From<JwtUser> for User {
             name: value.name,
             display_name: value.display_name,
             avatar: value.avatar,
+            public_flags: value.public_flags,
+            banner: value.banner,
+            avatar_decoration: value.avatar_decoration,
+            public_id: value.public_id,
+            is_admin: value.is_admin,
         }
     }
 }
@@ -51,40 +79,152 @@ impl From<User> for JwtUser {
             name: value.name,
             display_name: value.display_name,
             avatar: value.avatar,
+            public_flags: value.public_flags,
+            banner: value.banner,
+            avatar_decoration: value.avatar_decoration,
+            public_id: value.public_id,
+            is_admin: value.is_admin,
         }
     }
 }
 
-fn hmac_key(state: &AppState) -> anyhow::Result<Hmac<Sha256>> {
-    Hmac::new_from_slice(state.jwt_secret.as_bytes()).context("failed to create encryption key")
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    /// HMAC-SHA256 with a shared secret.
+    #[default]
+    Hs256,
+    /// RSA-SHA256, which lets third parties verify tokens with
+    /// the public key without being able to create them.
+    Rs256,
 }
 
-pub fn create(user: JwtUser, state: &AppState) -> AppResult<String> {
-    const EXPIRATION_TIME: Duration = Duration::from_secs(30 * 60); // 30 minutes
+impl FromStr for JwtAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "HS256" => Ok(JwtAlgorithm::Hs256),
+            "RS256" => Ok(JwtAlgorithm::Rs256),
+            _ => Err(anyhow!("unsupported JWT algorithm: {s}")),
+        }
+    }
+}
+
+/// The key used to sign and verify access tokens.
+pub enum JwtKey {
+    Hmac(Hmac<Sha256>),
+    Rsa {
+        signing: Box<SigningKey<Sha256>>,
+        verifying: Box<VerifyingKey<Sha256>>,
+    },
+}
+
+impl JwtKey {
+    pub fn hmac(secret: &str) -> anyhow::Result<Self> {
+        let key =
+            Hmac::new_from_slice(secret.as_bytes()).context("failed to create encryption key")?;
+
+        Ok(JwtKey::Hmac(key))
+    }
+
+    /// Loads a PEM-encoded RSA private key, in either PKCS#8 or PKCS#1 format.
+    pub fn rsa(private_key_pem: &str) -> anyhow::Result<Self> {
+        let private_key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(private_key_pem))
+            .context("failed to parse RSA private key")?;
+
+        let verifying = Box::new(VerifyingKey::new(private_key.to_public_key()));
+        let signing = Box::new(SigningKey::new(private_key));
+
+        Ok(JwtKey::Rsa { signing, verifying })
+    }
+}
+
+impl SigningAlgorithm for JwtKey {
+    fn algorithm_type(&self) -> AlgorithmType {
+        match self {
+            JwtKey::Hmac(_) => AlgorithmType::Hs256,
+            JwtKey::Rsa { .. } => AlgorithmType::Rs256,
+        }
+    }
+
+    fn sign(&self, header: &str, claims: &str) -> Result<String, jwt::Error> {
+        match self {
+            JwtKey::Hmac(key) => key.sign(header, claims),
+            JwtKey::Rsa { signing, .. } => {
+                let message = format!("{header}.{claims}");
+                let signature = signing
+                    .try_sign(message.as_bytes())
+                    .map_err(|_| jwt::Error::InvalidSignature)?;
+
+                Ok(BASE64_URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+            }
+        }
+    }
+}
+
+impl VerifyingAlgorithm for JwtKey {
+    fn algorithm_type(&self) -> AlgorithmType {
+        SigningAlgorithm::algorithm_type(self)
+    }
 
-    let key = hmac_key(state)?;
+    fn verify_bytes(
+        &self,
+        header: &str,
+        claims: &str,
+        signature: &[u8],
+    ) -> Result<bool, jwt::Error> {
+        match self {
+            JwtKey::Hmac(key) => key.verify_bytes(header, claims, signature),
+            JwtKey::Rsa { verifying, .. } => {
+                let Ok(signature) = Signature::try_from(signature) else {
+                    return Ok(false);
+                };
+
+                let message = format!("{header}.{claims}");
+                Ok(verifying.verify(message.as_bytes(), &signature).is_ok())
+            }
+        }
+    }
+}
+
+pub fn create(user: JwtUser, state: &AppState) -> AppResult<String> {
     let claims = JwtClaims {
         user,
         expiration: (Utc::now() + EXPIRATION_TIME).timestamp(),
     };
 
-    let jwt = claims.sign_with_key(&key).context("failed to sign JWT")?;
+    let jwt = claims
+        .sign_with_key(&*state.jwt_key)
+        .context("failed to sign JWT")?;
 
     Ok(jwt)
 }
 
-pub fn verify(token: &str, state: &AppState) -> AppResult<JwtClaims> {
-    let key = hmac_key(state)?;
+/// Checks the token's signature without checking if it's expired.
+pub fn decode(token: &str, state: &AppState) -> AppResult<JwtClaims> {
     let claims: JwtClaims = token
-        .verify_with_key(&key)
-        .map_err(|_| AppError::unauthorized("Token is invalid."))?;
+        .verify_with_key(&*state.jwt_key)
+        .map_err(|_| AppError::InvalidToken)?;
 
-    let expiration = DateTime::from_timestamp(claims.expiration, 0)
-        .ok_or_else(|| AppError::unauthorized("Token expiration time is invalid."))?;
+    Ok(claims)
+}
 
-    if Utc::now() < expiration {
-        Ok(claims)
+pub fn verify(token: &str, state: &AppState) -> AppResult<JwtClaims> {
+    let claims = decode(token, state)?;
+
+    if claims.is_expired()? {
+        Err(AppError::TokenExpired)
     } else {
-        Err(AppError::unauthorized("Token is expired."))
+        Ok(claims)
+    }
+}
+
+impl JwtClaims {
+    pub fn is_expired(&self) -> AppResult<bool> {
+        let expiration = DateTime::from_timestamp(self.expiration, 0)
+            .ok_or_else(|| AppError::unauthorized("Token expiration time is invalid."))?;
+
+        Ok(Utc::now() >= expiration)
     }
 }
@@ -0,0 +1,383 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fmt::Display,
+    fs,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::bail;
+use http::HeaderValue;
+use tracing::Level;
+use url::Url;
+
+use crate::{
+    auth::token::{JwtAlgorithm, JwtKey},
+    profile, socket,
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+const DEFAULT_THUNDERSTORE_BASE_URL: &str = "https://thunderstore.io";
+
+#[cfg(debug_assertions)]
+const DEFAULT_OAUTH_REDIRECT_URI: &str = "http://localhost:8080/api/auth/callback";
+
+#[cfg(not(debug_assertions))]
+const DEFAULT_OAUTH_REDIRECT_URI: &str = "http://gale.kesomannen.com/api/auth/callback";
+
+/// All settings of the server, read from environment variables at startup.
+///
+/// See `docs/hosting.md` for the full list of variables.
+pub struct Config {
+    pub port: u16,
+    pub log_level: Level,
+    pub database_url: String,
+    pub db_max_connections: u32,
+    /// How long requests wait for a free database connection before failing.
+    pub db_acquire_timeout: Duration,
+    /// `None` keeps idle connections open indefinitely.
+    pub db_idle_timeout: Option<Duration>,
+    pub redis_url: String,
+    pub discord_client_id: Arc<str>,
+    pub discord_client_secret: Arc<str>,
+    /// Space-separated, always includes `identify`.
+    pub discord_oauth_scopes: Arc<str>,
+    /// Must match a redirect registered in the Discord OAuth app.
+    pub oauth_redirect_uri: Arc<str>,
+    pub jwt_key: JwtKey,
+    pub supabase_url: Url,
+    pub supabase_api_key: Arc<str>,
+    pub storage_bucket_name: Arc<str>,
+    pub storage_max_retries: u32,
+    /// Only allow users in the `test_users` table to log in.
+    pub require_whitelist: bool,
+    /// Discord webhook that new and updated public profiles are announced to.
+    pub discord_announce_webhook: Option<Arc<str>>,
+    /// Without a trailing slash.
+    pub thunderstore_base_url: Arc<str>,
+    /// `None` disables CORS entirely.
+    pub cors_allowed_origins: Option<Vec<HeaderValue>>,
+    pub socket: socket::Config,
+    pub profile: profile::Config,
+}
+
+impl Config {
+    /// Reads and validates the configuration.
+    ///
+    /// Instead of stopping at the first problem, every missing or invalid
+    /// variable is collected and reported in a single error.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut vars = Env::default();
+
+        let port = vars.optional("PORT", 8080);
+        if port == 0 {
+            vars.error("PORT", "must be between 1 and 65535");
+        }
+
+        let log_level = vars.optional("LOG_LEVEL", Level::INFO);
+
+        let database_url = vars.required("DATABASE_URL");
+        vars.check_url("DATABASE_URL", &database_url);
+
+        let db_max_connections = vars.optional("DB_MAX_CONNECTIONS", 10);
+        if db_max_connections == 0 {
+            vars.error("DB_MAX_CONNECTIONS", "must be at least 1");
+        }
+        let db_acquire_timeout = Duration::from_secs(vars.optional("DB_ACQUIRE_TIMEOUT_SECS", 5));
+        let db_idle_timeout = vars.optional("DB_IDLE_TIMEOUT_SECS", 10 * 60);
+        let db_idle_timeout = (db_idle_timeout > 0).then(|| Duration::from_secs(db_idle_timeout));
+
+        let redis_url = vars.required("REDIS_URL");
+        vars.check_url("REDIS_URL", &redis_url);
+
+        let discord_client_id = vars.required("DISCORD_CLIENT_ID");
+        let discord_client_secret = vars.required("DISCORD_CLIENT_SECRET");
+        let discord_oauth_scopes = vars.optional("DISCORD_OAUTH_SCOPES", "identify".to_owned());
+        if !discord_oauth_scopes
+            .split_whitespace()
+            .any(|scope| scope == "identify")
+        {
+            vars.error("DISCORD_OAUTH_SCOPES", "must include identify");
+        }
+
+        let oauth_redirect_uri =
+            vars.optional("OAUTH_REDIRECT_URI", DEFAULT_OAUTH_REDIRECT_URI.to_owned());
+        vars.check_url("OAUTH_REDIRECT_URI", &oauth_redirect_uri);
+
+        let jwt_key = match vars.optional("JWT_ALGORITHM", JwtAlgorithm::default()) {
+            JwtAlgorithm::Hs256 => vars.jwt_key("JWT_SECRET", JwtKey::hmac),
+            JwtAlgorithm::Rs256 => vars.jwt_key("JWT_PRIVATE_KEY", JwtKey::rsa),
+        };
+
+        let supabase_url = vars.required("SUPABASE_URL");
+        let supabase_url = vars.check_url("SUPABASE_URL", &supabase_url);
+        let supabase_api_key = vars.required("SUPABASE_API_KEY");
+        let storage_bucket_name = vars.required("STORAGE_BUCKET_NAME");
+        let storage_max_retries = vars.optional("STORAGE_MAX_RETRIES", 3);
+
+        let require_whitelist = vars.optional("REQUIRE_WHITELIST", false);
+
+        let discord_announce_webhook = env::var("DISCORD_ANNOUNCE_WEBHOOK_URL").ok();
+        if let Some(url) = &discord_announce_webhook {
+            vars.check_url("DISCORD_ANNOUNCE_WEBHOOK_URL", url);
+        }
+
+        let thunderstore_base_url = vars.optional(
+            "THUNDERSTORE_BASE_URL",
+            DEFAULT_THUNDERSTORE_BASE_URL.to_owned(),
+        );
+        vars.check_url("THUNDERSTORE_BASE_URL", &thunderstore_base_url);
+
+        let cors_allowed_origins = env::var("CORS_ALLOWED_ORIGINS").ok().map(|str| {
+            str.split(',')
+                .filter_map(|origin| match origin.trim().parse() {
+                    Ok(origin) => Some(origin),
+                    Err(err) => {
+                        vars.error(
+                            "CORS_ALLOWED_ORIGINS",
+                            format!("{origin:?} is invalid: {err}"),
+                        );
+                        None
+                    }
+                })
+                .collect()
+        });
+
+        let mut socket = socket::Config::default();
+        socket.ping_interval = Duration::from_secs(
+            vars.optional("SOCKET_PING_INTERVAL_SECS", socket.ping_interval.as_secs()),
+        );
+        if socket.ping_interval.is_zero() {
+            vars.error("SOCKET_PING_INTERVAL_SECS", "must be at least 1");
+        }
+        socket.max_subscriptions =
+            vars.optional("SOCKET_MAX_SUBSCRIPTIONS", socket.max_subscriptions);
+        socket.coalesce_window = Duration::from_millis(vars.optional(
+            "SOCKET_COALESCE_WINDOW_MS",
+            socket.coalesce_window.as_millis() as u64,
+        ));
+
+        let mut profile = profile::Config::default();
+        profile.max_upload_size =
+            vars.optional("PROFILE_SIZE_LIMIT_BYTES", profile.max_upload_size);
+        profile.max_unpacked_size =
+            vars.optional("PROFILE_MAX_UNPACKED_SIZE_BYTES", profile.max_unpacked_size);
+        profile.max_versions = vars.optional("PROFILE_MAX_VERSIONS", profile.max_versions);
+        profile.cache_ttl = Duration::from_secs(
+            vars.optional("PROFILE_CACHE_TTL_SECS", profile.cache_ttl.as_secs()),
+        );
+        let restore_window_days = vars.optional(
+            "PROFILE_RESTORE_WINDOW_DAYS",
+            profile.restore_window.as_secs() / SECONDS_PER_DAY,
+        );
+        profile.restore_window = Duration::from_secs(restore_window_days * SECONDS_PER_DAY);
+        profile.allowed_communities = vars.allowed_communities();
+        profile.id_format = vars.id_format();
+        profile.reject_profanity =
+            vars.optional("PROFILE_REJECT_PROFANITY", profile.reject_profanity);
+        let storage_quota = vars.optional("USER_STORAGE_QUOTA_BYTES", 0);
+        profile.storage_quota = (storage_quota > 0).then_some(storage_quota);
+        profile.community_images = vars.community_images();
+
+        if !vars.errors.is_empty() {
+            bail!("invalid configuration:\n  {}", vars.errors.join("\n  "));
+        }
+
+        Ok(Self {
+            port,
+            log_level,
+            database_url,
+            db_max_connections,
+            db_acquire_timeout,
+            db_idle_timeout,
+            redis_url,
+            discord_client_id: discord_client_id.into(),
+            discord_client_secret: discord_client_secret.into(),
+            discord_oauth_scopes: discord_oauth_scopes
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .into(),
+            oauth_redirect_uri: oauth_redirect_uri.into(),
+            jwt_key: jwt_key.expect("errors are checked above"),
+            supabase_url: supabase_url.expect("errors are checked above"),
+            supabase_api_key: supabase_api_key.into(),
+            storage_bucket_name: storage_bucket_name.into(),
+            storage_max_retries,
+            require_whitelist,
+            discord_announce_webhook: discord_announce_webhook.map(Into::into),
+            thunderstore_base_url: thunderstore_base_url.trim_end_matches('/').into(),
+            cors_allowed_origins,
+            socket,
+            profile,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Env {
+    errors: Vec<String>,
+}
+
+impl Env {
+    fn error(&mut self, name: &str, message: impl Display) {
+        self.errors.push(format!("{name} {message}"));
+    }
+
+    /// Returns an empty string if the variable is missing, which is fine
+    /// since [`Config::from_env`] bails before the value is used.
+    fn required(&mut self, name: &str) -> String {
+        match env::var(name) {
+            Ok(value) if !value.trim().is_empty() => value,
+            Ok(_) => {
+                self.error(name, "is empty");
+                String::new()
+            }
+            Err(_) => {
+                self.error(name, "is not set");
+                String::new()
+            }
+        }
+    }
+
+    fn optional<T>(&mut self, name: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match env::var(name) {
+            Ok(value) => value.parse().unwrap_or_else(|err| {
+                self.error(name, format!("is invalid: {err}"));
+                default
+            }),
+            Err(_) => default,
+        }
+    }
+
+    /// Merges the communities listed in `PROFILE_ALLOWED_COMMUNITIES` (comma-separated)
+    /// and the JSON array in the file at `PROFILE_ALLOWED_COMMUNITIES_FILE`.
+    fn allowed_communities(&mut self) -> Option<HashSet<String>> {
+        let list = env::var("PROFILE_ALLOWED_COMMUNITIES").ok();
+        let path = env::var("PROFILE_ALLOWED_COMMUNITIES_FILE").ok();
+
+        if list.is_none() && path.is_none() {
+            return None;
+        }
+
+        let mut communities: HashSet<String> = list
+            .iter()
+            .flat_map(|list| list.split(','))
+            .map(|community| community.trim().to_owned())
+            .filter(|community| !community.is_empty())
+            .collect();
+
+        if let Some(path) = path {
+            let result = fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(serde_json::from_str::<Vec<String>>(&json)?));
+
+            match result {
+                Ok(list) => communities.extend(list),
+                Err(err) => self.error(
+                    "PROFILE_ALLOWED_COMMUNITIES_FILE",
+                    format!("could not be read from {path}: {err}"),
+                ),
+            }
+        }
+
+        Some(communities)
+    }
+
+    /// Reads the JSON object of community names to image URLs in the file at `COMMUNITY_IMAGES_FILE`.
+    fn community_images(&mut self) -> HashMap<String, String> {
+        let Ok(path) = env::var("COMMUNITY_IMAGES_FILE") else {
+            return HashMap::new();
+        };
+
+        let result = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(serde_json::from_str::<HashMap<String, String>>(&json)?));
+
+        match result {
+            Ok(images) => images,
+            Err(err) => {
+                self.error(
+                    "COMMUNITY_IMAGES_FILE",
+                    format!("could not be read from {path}: {err}"),
+                );
+                HashMap::new()
+            }
+        }
+    }
+
+    fn id_format(&mut self) -> profile::IdFormat {
+        let default = profile::IdFormat::default();
+
+        let length = self.optional("PROFILE_ID_LENGTH", default.length);
+        if !profile::IdFormat::LENGTH_RANGE.contains(&length) {
+            let range = profile::IdFormat::LENGTH_RANGE;
+            self.error(
+                "PROFILE_ID_LENGTH",
+                format!("must be between {} and {}", range.start(), range.end()),
+            );
+        }
+
+        let alphabet = match env::var("PROFILE_ID_ALPHABET") {
+            Ok(str) => {
+                let mut alphabet: Vec<char> = str.chars().collect();
+                alphabet.sort_unstable();
+                alphabet.dedup();
+
+                if alphabet.len() < 2 || !alphabet.iter().all(char::is_ascii_alphanumeric) {
+                    self.error(
+                        "PROFILE_ID_ALPHABET",
+                        "must contain at least 2 distinct alphanumeric characters",
+                    );
+                }
+
+                alphabet
+            }
+            Err(_) => default.alphabet,
+        };
+
+        profile::IdFormat { length, alphabet }
+    }
+
+    fn check_url(&mut self, name: &str, value: &str) -> Option<Url> {
+        if value.is_empty() {
+            // already reported as missing
+            return None;
+        }
+
+        match Url::parse(value) {
+            Ok(url) => Some(url),
+            Err(err) => {
+                self.error(name, format!("is not a valid URL: {err}"));
+                None
+            }
+        }
+    }
+
+    fn jwt_key(
+        &mut self,
+        name: &str,
+        parse: impl FnOnce(&str) -> anyhow::Result<JwtKey>,
+    ) -> Option<JwtKey> {
+        let value = self.required(name);
+
+        if value.is_empty() {
+            return None;
+        }
+
+        match parse(&value) {
+            Ok(key) => Some(key),
+            Err(err) => {
+                self.error(name, format!("is invalid: {err:#}"));
+                None
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::Arc};
 
 use axum::{
     http::StatusCode,
@@ -7,6 +7,8 @@ use axum::{
 };
 use serde::Serialize;
 
+use crate::request_id;
+
 pub type AppResult<T> = Result<T, AppError>;
 
 pub type CowStr = Cow<'static, str>;
@@ -34,7 +36,37 @@ pub enum AppError {
     })]
     Forbidden { reason: Option<CowStr> },
 
-    #[error("Something went wrong.")]
+    #[error("Authorization header is missing.")]
+    MissingAuthorization,
+
+    #[error("Token is invalid.")]
+    InvalidToken,
+
+    #[error("Token is expired.")]
+    TokenExpired,
+
+    #[error("Invalid refresh token.")]
+    InvalidRefreshToken,
+
+    #[error("Refresh token is expired.")]
+    RefreshTokenExpired,
+
+    #[error("{}", match reason {
+        Some(reason) => reason,
+        None => "Request body is too large."
+    })]
+    PayloadTooLarge { reason: Option<CowStr> },
+
+    #[error("{}", match reason {
+        Some(reason) => reason,
+        None => "Too many requests."
+    })]
+    TooManyRequests { reason: Option<CowStr> },
+
+    #[error("{}", match .0 {
+        sqlx::Error::PoolTimedOut => "The server is busy. Please try again later.",
+        _ => "Something went wrong."
+    })]
     Sqlx(#[from] sqlx::Error),
 
     #[error("Something went wrong.")]
@@ -63,37 +95,88 @@ impl AppError {
         }
     }
 
-    fn status(&self) -> StatusCode {
+    pub fn payload_too_large(reason: impl Into<CowStr>) -> Self {
+        AppError::PayloadTooLarge {
+            reason: Some(reason.into()),
+        }
+    }
+
+    pub fn too_many_requests(reason: impl Into<CowStr>) -> Self {
+        AppError::TooManyRequests {
+            reason: Some(reason.into()),
+        }
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
         match self {
             AppError::NotFound => StatusCode::NOT_FOUND,
             AppError::BadRequest { .. } => StatusCode::BAD_REQUEST,
             AppError::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
             AppError::Forbidden { .. } => StatusCode::FORBIDDEN,
+            AppError::MissingAuthorization
+            | AppError::InvalidToken
+            | AppError::TokenExpired
+            | AppError::RefreshTokenExpired => StatusCode::UNAUTHORIZED,
+            AppError::InvalidRefreshToken => StatusCode::BAD_REQUEST,
+            AppError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            // every connection is busy, which is temporary
+            AppError::Sqlx(sqlx::Error::PoolTimedOut) => StatusCode::SERVICE_UNAVAILABLE,
             AppError::Sqlx(_) | AppError::Reqwest(_) | AppError::Other(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
         }
     }
+
+    /// Logs internal errors, since their details aren't shown to the client.
+    pub(crate) fn log(&self) {
+        match self {
+            AppError::Sqlx(err) => tracing::error!("database error: {err:#}"),
+            AppError::Reqwest(err) => tracing::error!("http error: {err:#}"),
+            AppError::Other(err) => tracing::error!("unexpected server error: {err:#}"),
+            _ => (),
+        }
+    }
+
+    /// A machine-readable identifier for the error, so clients don't have to match on messages.
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound => "not_found",
+            AppError::BadRequest { .. } => "bad_request",
+            AppError::Unauthorized { .. } => "unauthorized",
+            AppError::Forbidden { .. } => "forbidden",
+            AppError::MissingAuthorization => "missing_authorization",
+            AppError::InvalidToken => "invalid_token",
+            AppError::TokenExpired => "token_expired",
+            AppError::InvalidRefreshToken => "invalid_refresh_token",
+            AppError::RefreshTokenExpired => "refresh_token_expired",
+            AppError::PayloadTooLarge { .. } => "payload_too_large",
+            AppError::TooManyRequests { .. } => "too_many_requests",
+            AppError::Sqlx(sqlx::Error::PoolTimedOut) => "service_unavailable",
+            AppError::Sqlx(_) | AppError::Reqwest(_) | AppError::Other(_) => "internal_error",
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 struct ErrorResponse {
+    code: &'static str,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<Arc<str>>,
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        match &self {
-            AppError::Sqlx(err) => tracing::error!("database error: {err:#}"),
-            AppError::Reqwest(err) => tracing::error!("http error: {err:#}"),
-            AppError::Other(err) => tracing::error!("unexpected server error: {err:#}"),
-            _ => (),
-        }
+        self.log();
 
         (
             self.status(),
             Json(ErrorResponse {
+                code: self.code(),
                 message: self.to_string(),
+                request_id: request_id::current(),
             }),
         )
             .into_response()
@@ -1,39 +1,67 @@
 use std::sync::Arc;
 
-use axum::Router;
+use axum::{middleware, Router};
 use sqlx::PgPool;
 
-mod auth;
+pub mod auth;
+pub mod config;
 mod error;
+pub mod metrics;
 pub mod migrate;
-mod profile;
+pub mod profile;
+mod rate_limit;
 mod redirect;
+pub mod request_id;
+mod rfc3339;
 mod routes;
 mod short_uuid;
 pub mod socket;
 pub mod storage;
+#[cfg(test)]
+mod testing;
+pub mod thunderstore;
+pub mod webhook;
 
-type RedisConn = redis::aio::MultiplexedConnection;
+type RedisConn = redis::aio::ConnectionManager;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
     pub storage: storage::Client,
     pub http: reqwest::Client,
+    /// Used for user-provided webhook URLs, see [`webhook::client`].
+    pub webhook_http: reqwest::Client,
     pub discord_client_id: Arc<str>,
     pub discord_client_secret: Arc<str>,
-    pub jwt_secret: Arc<str>,
+    pub discord_oauth_scopes: Arc<str>,
+    pub oauth_redirect_uri: Arc<str>,
+    pub jwt_key: Arc<auth::token::JwtKey>,
+    /// Only allow users in the `test_users` table to log in.
+    pub require_whitelist: bool,
+    pub discord_announce_webhook: Option<Arc<str>>,
+    /// Thunderstore instance that profiles are mirrored to and packages are looked up on.
+    pub thunderstore_base_url: Arc<str>,
     pub sockets: socket::State,
+    pub profile_config: Arc<profile::Config>,
+    pub metrics: Arc<metrics::Metrics>,
     pub redis: RedisConn,
+    pub migrations: migrate::Jobs,
 }
 
 pub fn routes(state: AppState) -> Router {
+    let upload_size_limit = state.profile_config.max_upload_size;
+
     Router::new()
         .nest("/auth", routes::auth::routes())
-        .nest("/profile", routes::profile::routes())
+        .nest("/profile", routes::profile::routes(upload_size_limit))
         .nest("/user", routes::user::routes())
+        .nest("/communities", routes::community::routes())
         .nest("/desktop", routes::desktop::routes())
         .nest("/socket", routes::socket::routes())
+        .nest("/health", routes::health::routes())
+        .nest("/metrics", routes::metrics::routes())
+        .nest("/admin", routes::admin::routes())
+        .layer(middleware::from_fn(request_id::middleware))
         .with_state(state)
 }
 
@@ -1,15 +1,23 @@
-use std::{env, sync::Arc, time::Instant};
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use anyhow::{anyhow, Context};
+use anyhow::Context;
 use axum::Router;
 use dotenvy::dotenv;
-use gale_sync::AppState;
-use sqlx::PgPool;
+use gale_sync::{config::Config, request_id, AppState};
+use http::{
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    HeaderName, Method,
+};
+use sqlx::{postgres::PgPoolOptions, PgPool};
 use tokio::sync::mpsc;
-use tower_http::{services::ServeDir, trace::TraceLayer};
-use tracing::{debug, info, Level};
+use tower_http::{cors::CorsLayer, services::ServeDir, trace::TraceLayer};
+use tracing::{debug, info};
 
-const DEFAULT_PORT: u16 = 8080;
+const REDIS_MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -17,16 +25,14 @@ async fn main() -> anyhow::Result<()> {
 
     dotenv().ok();
 
-    let log_level = env_var("LOG_LEVEL")
-        .map(|str| {
-            str.parse()
-                .expect("LOG_LEVEL variable is not a valid log level")
-        })
-        .unwrap_or(Level::INFO);
+    let config = Config::from_env()?;
+
+    // needs to happen before any profile ids are parsed
+    config.profile.id_format.clone().install();
 
     tracing_subscriber::fmt()
         .compact()
-        .with_max_level(log_level)
+        .with_max_level(config.log_level)
         .init();
 
     info!(
@@ -38,46 +44,87 @@ async fn main() -> anyhow::Result<()> {
 
     let (redis_tx, redis_rx) = mpsc::unbounded_channel();
 
-    let (db, redis) = tokio::try_join!(setup_db(), setup_redis(redis_tx))?;
+    let (db, redis) =
+        tokio::try_join!(setup_db(&config), setup_redis(&config.redis_url, redis_tx))?;
+
+    let sockets = gale_sync::socket::State::new(redis_rx, redis.clone(), config.socket);
 
-    let sockets = gale_sync::socket::State::new(redis_rx);
+    let metrics = gale_sync::metrics::Metrics::new()?;
 
     let http = reqwest::Client::new();
+    let webhook_http = gale_sync::webhook::client()?;
 
     let storage = gale_sync::storage::Client::new(
-        env_var_arc("STORAGE_BUCKET_NAME")?,
-        env_var_arc("SUPABASE_API_KEY")?,
-        format!("{}/storage/v1", env_var("SUPABASE_URL")?).into(),
+        config.storage_bucket_name,
+        config.supabase_api_key,
+        format!(
+            "{}/storage/v1",
+            config.supabase_url.as_str().trim_end_matches('/')
+        )
+        .into(),
         http.clone(),
-    );
+    )
+    .with_latency_histogram(metrics.storage_request_duration.clone())
+    .with_max_retries(config.storage_max_retries);
 
     let state = AppState {
         db,
         http,
+        webhook_http,
         storage,
-        discord_client_id: env_var_arc("DISCORD_CLIENT_ID")?,
-        discord_client_secret: env_var_arc("DISCORD_CLIENT_SECRET")?,
-        jwt_secret: env_var_arc("JWT_SECRET")?,
+        discord_client_id: config.discord_client_id,
+        discord_client_secret: config.discord_client_secret,
+        discord_oauth_scopes: config.discord_oauth_scopes,
+        oauth_redirect_uri: config.oauth_redirect_uri,
+        jwt_key: Arc::new(config.jwt_key),
+        require_whitelist: config.require_whitelist,
+        discord_announce_webhook: config.discord_announce_webhook,
+        thunderstore_base_url: config.thunderstore_base_url,
         sockets,
+        profile_config: Arc::new(config.profile),
+        metrics: Arc::new(metrics),
         redis,
+        migrations: Default::default(),
     };
 
-    if env::args().into_iter().nth(1).as_deref() == Some("--migrate") {
+    if env::args().nth(1).as_deref() == Some("--migrate") {
         gale_sync::migrate::migrate(&state).await?;
     }
 
-    let app = Router::new()
+    tokio::spawn(gale_sync::profile::run_purge_task(state.clone()));
+
+    let mut app = Router::new()
         .nest("/api", gale_sync::routes(state))
-        .fallback_service(ServeDir::new("public"))
-        .layer(TraceLayer::new_for_http());
+        .fallback_service(ServeDir::new("public"));
+
+    // CORS is only needed for browser clients, so it's opt-in
+    if let Some(origins) = config.cors_allowed_origins {
+        app = app.layer(
+            CorsLayer::new()
+                .allow_origin(origins)
+                .allow_methods([
+                    Method::GET,
+                    Method::POST,
+                    Method::PUT,
+                    Method::PATCH,
+                    Method::DELETE,
+                ])
+                .allow_headers([
+                    AUTHORIZATION,
+                    CONTENT_TYPE,
+                    ACCEPT,
+                    request_id::HEADER,
+                    HeaderName::from_static("idempotency-key"),
+                ])
+                .expose_headers([request_id::HEADER]),
+        );
+    }
 
-    let port = env_var("PORT")
-        .map(|str| str.parse().expect("PORT variable is not a valid integer"))
-        .unwrap_or(DEFAULT_PORT);
+    let app = app.layer(TraceLayer::new_for_http());
 
-    info!("listening on port {port}");
+    info!("listening on port {}", config.port);
 
-    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", config.port)).await?;
 
     info!("ready to serve in {:?}", start.elapsed());
 
@@ -86,40 +133,41 @@ async fn main() -> anyhow::Result<()> {
 }
 
 async fn setup_redis(
+    url: &str,
     sender: impl redis::aio::AsyncPushSender,
-) -> anyhow::Result<redis::aio::MultiplexedConnection> {
-    let url = env_var("REDIS_URL")?;
-
+) -> anyhow::Result<redis::aio::ConnectionManager> {
     debug!("connecting to redis at {url}");
 
+    // the manager reconnects on its own and re-issues the subscriptions below when it does
+    let config = redis::aio::ConnectionManagerConfig::new()
+        .set_push_sender(sender)
+        .set_automatic_resubscription()
+        .set_max_delay(REDIS_MAX_RECONNECT_DELAY.as_millis() as u64);
+
     let mut redis = redis::Client::open(url)?
-        .get_multiplexed_async_connection_with_config(
-            &redis::AsyncConnectionConfig::new().set_push_sender(sender),
-        )
+        .get_connection_manager_with_config(config)
         .await
         .context("failed to establish redis connection")?;
 
     redis.psubscribe("profile-update:*").await?;
     redis.psubscribe("profile-delete:*").await?;
+    redis.psubscribe("collaborators-changed:*").await?;
+    redis.psubscribe("user-profile-update:*").await?;
+    redis.psubscribe("user-profile-delete:*").await?;
 
     Ok(redis)
 }
 
-async fn setup_db() -> anyhow::Result<PgPool> {
-    let db_url = env_var("DATABASE_URL")?;
+async fn setup_db(config: &Config) -> anyhow::Result<PgPool> {
+    debug!("connecting to database at {}", config.database_url);
 
-    debug!("connecting to database at {db_url}");
-
-    let db = PgPool::connect(&db_url).await?;
+    let db = PgPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .acquire_timeout(config.db_acquire_timeout)
+        .idle_timeout(config.db_idle_timeout)
+        .connect(&config.database_url)
+        .await?;
 
     //sqlx::migrate!().run(&db).await?;
     Ok(db)
 }
-
-fn env_var_arc(name: &str) -> anyhow::Result<Arc<str>> {
-    env_var(name).map(Into::into)
-}
-
-fn env_var(name: &str) -> anyhow::Result<String> {
-    std::env::var(name).map_err(|_| anyhow!("{name} is not set"))
-}
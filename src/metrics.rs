@@ -0,0 +1,68 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+/// Prometheus metrics exposed at `GET /api/metrics`.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub profile_uploads: IntCounter,
+    pub profile_downloads: IntCounter,
+    pub socket_connections: IntGauge,
+    auth_attempts: IntCounterVec,
+    pub storage_request_duration: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("gale_sync".into()), None)?;
+
+        let profile_uploads = IntCounter::new(
+            "profile_uploads_total",
+            "Number of profile archives uploaded",
+        )?;
+        let profile_downloads =
+            IntCounter::new("profile_downloads_total", "Number of profiles downloaded")?;
+        let socket_connections =
+            IntGauge::new("socket_connections", "Number of open WebSocket connections")?;
+        let auth_attempts = IntCounterVec::new(
+            Opts::new(
+                "auth_attempts_total",
+                "Number of logins and token refreshes",
+            ),
+            &["result"],
+        )?;
+        let storage_request_duration = Histogram::with_opts(HistogramOpts::new(
+            "storage_request_duration_seconds",
+            "Latency of requests to the storage API, including retries",
+        ))?;
+
+        registry.register(Box::new(profile_uploads.clone()))?;
+        registry.register(Box::new(profile_downloads.clone()))?;
+        registry.register(Box::new(socket_connections.clone()))?;
+        registry.register(Box::new(auth_attempts.clone()))?;
+        registry.register(Box::new(storage_request_duration.clone()))?;
+
+        Ok(Self {
+            registry,
+            profile_uploads,
+            profile_downloads,
+            socket_connections,
+            auth_attempts,
+            storage_request_duration,
+        })
+    }
+
+    pub fn record_auth(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.auth_attempts.with_label_values(&[result]).inc();
+    }
+
+    /// Encodes all metrics in the Prometheus text format.
+    pub fn encode(&self) -> prometheus::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer).expect("prometheus text output is valid utf-8"))
+    }
+}
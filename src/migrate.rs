@@ -1,53 +1,147 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::bail;
 use axum::body::Bytes;
 use futures_util::StreamExt;
+use serde::Serialize;
 use tokio::sync::mpsc;
 use tracing::{error, info};
 use uuid::Uuid;
 
 use crate::{prelude::*, profile::ProfileId};
 
-async fn download_task(tx: mpsc::Sender<(Uuid, Bytes)>, state: AppState) -> anyhow::Result<()> {
+/// Progress of a migration job.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub migrated: u64,
+    pub errors: Vec<String>,
+    pub finished: bool,
+}
+
+/// Migration jobs started through the admin API, kept in memory.
+#[derive(Clone, Default)]
+pub struct Jobs(Arc<Mutex<HashMap<Uuid, Arc<Mutex<JobStatus>>>>>);
+
+impl Jobs {
+    /// Starts migrating in the background and returns the id of the job.
+    pub fn start(&self, state: AppState) -> Uuid {
+        let id = Uuid::new_v4();
+        let status = Arc::new(Mutex::new(JobStatus::default()));
+
+        self.0.lock().unwrap().insert(id, status.clone());
+
+        tokio::spawn(async move {
+            run(&state, &status).await;
+            info!("migration job {id} finished");
+        });
+
+        id
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<JobStatus> {
+        let jobs = self.0.lock().unwrap();
+        let status = jobs.get(id)?.lock().unwrap().clone();
+        Some(status)
+    }
+}
+
+fn record_error(status: &Mutex<JobStatus>, message: String) {
+    error!("{message}");
+    status.lock().unwrap().errors.push(message);
+}
+
+async fn download_task(
+    tx: mpsc::Sender<(Uuid, Bytes)>,
+    state: AppState,
+    status: Arc<Mutex<JobStatus>>,
+) -> anyhow::Result<()> {
     let mut profiles = sqlx::query!(
-        r#"SELECT id, short_id AS "short_id: ProfileId" FROM profiles WHERE code IS NULL"#
+        r#"SELECT
+            id,
+            short_id AS "short_id: ProfileId",
+            storage_key
+        FROM profiles
+        WHERE code IS NULL"#
     )
     .fetch(&state.db);
 
     while let Some(profile) = profiles.next().await.transpose()? {
-        let path = crate::profile::storage_key(&profile.short_id);
+        let path = crate::profile::current_storage_key(&profile.short_id, profile.storage_key);
 
-        let archive = state.storage.download(path).await?;
-        tx.send((profile.id, archive)).await?;
+        match state.storage.download(path).await {
+            Ok(archive) => tx.send((profile.id, archive)).await?,
+            Err(err) => record_error(
+                &status,
+                format!("failed to download profile {}: {err:#}", profile.short_id),
+            ),
+        }
     }
 
     Ok(())
 }
 
-pub async fn migrate(state: &AppState) -> anyhow::Result<()> {
+/// Uploads every profile without a Thunderstore code and stores the resulting code.
+///
+/// Failures of individual profiles are recorded in `status` without stopping the migration.
+async fn run(state: &AppState, status: &Arc<Mutex<JobStatus>>) {
     let (tx, mut rx) = mpsc::channel(32);
 
     let state2 = state.to_owned();
+    let status2 = status.clone();
     tokio::spawn(async move {
-        if let Err(err) = download_task(tx, state2).await {
-            error!("download error: {err}")
+        if let Err(err) = download_task(tx, state2, status2.clone()).await {
+            record_error(&status2, format!("download error: {err:#}"));
         }
     });
 
-    let mut count = 0;
-
     while let Some((profile_id, bytes)) = rx.recv().await {
-        let key = crate::profile::upload(state, bytes).await?;
+        let result = async {
+            let key = crate::profile::upload(state, bytes).await?;
+
+            sqlx::query!(
+                "UPDATE profiles SET code = $1 WHERE id = $2",
+                key,
+                profile_id
+            )
+            .execute(&state.db)
+            .await?;
+
+            AppResult::Ok(())
+        }
+        .await;
 
-        sqlx::query!(
-            "UPDATE profiles SET code = $1 WHERE id = $2",
-            key,
-            profile_id
-        )
-        .execute(&state.db)
-        .await?;
+        match result {
+            Ok(()) => {
+                let mut status = status.lock().unwrap();
+                info!("migrated profile #{}", status.migrated);
+                status.migrated += 1;
+            }
+            Err(err) => record_error(
+                status,
+                format!("failed to migrate profile {profile_id}: {err:?}"),
+            ),
+        }
+    }
+
+    status.lock().unwrap().finished = true;
+}
+
+pub async fn migrate(state: &AppState) -> anyhow::Result<()> {
+    let status = Arc::new(Mutex::new(JobStatus::default()));
 
-        info!("migrated profile #{count}",);
+    run(state, &status).await;
 
-        count += 1;
+    let status = status.lock().unwrap();
+    if !status.errors.is_empty() {
+        bail!(
+            "{} profiles failed to migrate ({} succeeded)",
+            status.errors.len(),
+            status.migrated
+        );
     }
 
     Ok(())
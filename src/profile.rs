@@ -1,26 +1,149 @@
-use std::{borrow::Cow, fmt::Display, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    future::Future,
+    ops::RangeInclusive,
+    path::Path,
+    str::FromStr,
+    sync::OnceLock,
+    time::Duration,
+};
 
+use anyhow::Context;
 use axum::body::Bytes;
+use base64::prelude::*;
 use chrono::{DateTime, Utc};
-use http::StatusCode;
+use http::{header::CONTENT_LENGTH, StatusCode};
+use rand::seq::IndexedRandom;
+use redis::AsyncCommands;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use sqlx::{
     encode::IsNull,
     error::BoxDynError,
-    postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef},
+    postgres::{types::PgInterval, PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef},
     prelude::*,
     Encode, Postgres, Type,
 };
+use tokio_util::io::ReaderStream;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use crate::{auth::User, prelude::*, short_uuid::ShortUuid, AppState};
+use crate::{auth::User, prelude::*, short_uuid::ShortUuid, thunderstore, AppState};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The maximum size of an uploaded archive.
+    pub max_upload_size: usize,
+    /// The maximum total uncompressed size of an uploaded archive.
+    pub max_unpacked_size: u64,
+    /// How many past versions of each profile are kept for rollbacks.
+    pub max_versions: i32,
+    /// How long metadata is cached in Redis. Zero disables the cache.
+    pub cache_ttl: Duration,
+    /// How long deleted profiles can be restored before they're purged.
+    pub restore_window: Duration,
+    /// Thunderstore communities that profiles may belong to. `None` allows any community.
+    pub allowed_communities: Option<HashSet<String>>,
+    /// The format of newly generated profile ids.
+    pub id_format: IdFormat,
+    /// Reject profile names and communities that contain profanity.
+    pub reject_profanity: bool,
+    /// The maximum total size of a user's profile archives. `None` means unlimited.
+    pub storage_quota: Option<u64>,
+    /// Link preview images of communities, used by the desktop clone links.
+    pub community_images: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn is_community_allowed(&self, community: &str) -> bool {
+        self.allowed_communities
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(community))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_upload_size: 10 * 1024 * 1024,
+            max_unpacked_size: 100 * 1024 * 1024,
+            max_versions: 10,
+            cache_ttl: Duration::from_secs(5 * 60),
+            restore_window: Duration::from_secs(30 * 24 * 60 * 60),
+            allowed_communities: None,
+            id_format: IdFormat::default(),
+            reject_profanity: false,
+            storage_quota: None,
+            community_images: HashMap::new(),
+        }
+    }
+}
+
+/// The length and characters of short profile ids.
+#[derive(Debug, Clone)]
+pub struct IdFormat {
+    pub length: usize,
+    pub alphabet: Vec<char>,
+}
+
+static ID_FORMAT: OnceLock<IdFormat> = OnceLock::new();
+
+impl IdFormat {
+    /// Allowed range for `length`. Ids must stay shorter than base64 encoded UUIDs
+    /// so the two can't be mixed up.
+    pub const LENGTH_RANGE: RangeInclusive<usize> = 4..=16;
+
+    /// Sets the format used to generate and validate ids for the rest of the program.
+    ///
+    /// Ids are deserialized without access to the app state, which is why this is global.
+    pub fn install(self) {
+        if ID_FORMAT.set(self).is_err() {
+            warn!("profile id format was already installed");
+        }
+    }
+
+    /// The installed format, or the default if none was installed.
+    pub fn current() -> &'static IdFormat {
+        ID_FORMAT.get_or_init(IdFormat::default)
+    }
+
+    pub fn generate(&self) -> String {
+        let mut rng = rand::rng();
+
+        (0..self.length)
+            .map(|_| {
+                *self
+                    .alphabet
+                    .choose(&mut rng)
+                    .expect("alphabet is not empty")
+            })
+            .collect()
+    }
+
+    pub fn matches(&self, id: &str) -> bool {
+        id.chars().count() == self.length && id.chars().all(|c| self.alphabet.contains(&c))
+    }
+}
+
+impl Default for IdFormat {
+    fn default() -> Self {
+        Self {
+            length: 6,
+            alphabet: ('A'..='Z').chain('0'..='9').collect(),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(try_from = "String", into = "String")]
 pub enum ProfileId {
-    /// A 6-character alphanumeric string.
+    /// A short alphanumeric string, see [`IdFormat`].
+    ///
+    /// Ids created before the format was configurable are 6 characters long.
     Short(String),
     /// A base64-encoded UUID.
     ///
@@ -41,13 +164,16 @@ impl TryFrom<String> for ProfileId {
     type Error = AppError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        if Regex::new(r"^[a-zA-Z0-9]{6}$").unwrap().is_match(&value) {
+        let format = IdFormat::current();
+
+        if format.matches(&value) || Regex::new(r"^[a-zA-Z0-9]{6}$").unwrap().is_match(&value) {
             Ok(ProfileId::Short(value))
         } else if let Ok(short_uuid) = ShortUuid::try_from(value.clone()) {
             Ok(ProfileId::Legacy(short_uuid))
         } else {
             Err(AppError::bad_request(format!(
-                "Invalid profile id: {value}. Must be a base-64 encoded UUID or a 6-character alphanumeric string."
+                "Invalid profile id: {value}. Must be a base-64 encoded UUID or a {}-character alphanumeric string.",
+                format.length
             )))
         }
     }
@@ -110,14 +236,74 @@ impl Decode<'_, Postgres> for ProfileId {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "profile_visibility", rename_all = "lowercase")]
+pub enum Visibility {
+    /// Anyone can view and download the profile.
+    #[default]
+    Public,
+    /// Like `Public`, but the profile isn't listed anywhere.
+    Unlisted,
+    /// Only the owner can view and download the profile.
+    Private,
+}
+
+/// Always serialized as an object, but also deserialized from `"major.minor.patch"`
+/// strings, which some mod managers export instead.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", try_from = "RawModVersion")]
 pub struct ModVersion {
     pub major: u32,
     pub minor: u32,
     pub patch: u32,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawModVersion {
+    Object { major: u32, minor: u32, patch: u32 },
+    String(String),
+}
+
+impl TryFrom<RawModVersion> for ModVersion {
+    type Error = String;
+
+    fn try_from(value: RawModVersion) -> Result<Self, Self::Error> {
+        match value {
+            RawModVersion::Object {
+                major,
+                minor,
+                patch,
+            } => Ok(ModVersion {
+                major,
+                minor,
+                patch,
+            }),
+            RawModVersion::String(str) => str.parse(),
+        }
+    }
+}
+
+impl FromStr for ModVersion {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid mod version {str:?}, expected major.minor.patch");
+
+        let mut parts = str.split('.').map(|part| part.parse::<u32>());
+
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) => Ok(ModVersion {
+                major,
+                minor,
+                patch,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ProfileMod {
@@ -140,40 +326,194 @@ pub struct ProfileManifest {
 pub struct ProfileMetadata {
     #[serde(rename = "id")]
     pub short_id: ProfileId,
+    #[serde(with = "crate::rfc3339")]
     pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::rfc3339")]
     pub updated_at: DateTime<Utc>,
+    pub visibility: Visibility,
+    pub downloads: i32,
     pub owner: User,
+    pub collaborators: Vec<User>,
+    /// Public id of the user who made the latest change, usually the owner or a collaborator.
+    pub updated_by: Option<ShortUuid>,
+    #[serde(default)]
+    pub config_files: ConfigFiles,
     pub manifest: ProfileManifest,
 }
 
+/// The files in a profile's archive besides the manifest, usually mod configs.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigFiles {
+    pub count: i32,
+    /// Total uncompressed size.
+    pub size_bytes: i64,
+}
+
+impl ProfileMetadata {
+    pub fn is_visible_to(&self, user: Option<&User>) -> bool {
+        self.visibility != Visibility::Private || user.is_some_and(|user| user.id == self.owner.id)
+    }
+}
+
+/// Some user fields aren't serialized, so they're stored separately in the cache.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedMetadata {
+    metadata: ProfileMetadata,
+    owner: HiddenUserFields,
+    collaborators: Vec<HiddenUserFields>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HiddenUserFields {
+    id: i32,
+    is_admin: bool,
+}
+
+impl HiddenUserFields {
+    fn new(user: &User) -> Self {
+        HiddenUserFields {
+            id: user.id,
+            is_admin: user.is_admin,
+        }
+    }
+
+    fn restore(self, user: &mut User) {
+        user.id = self.id;
+        user.is_admin = self.is_admin;
+    }
+}
+
+pub(crate) fn cache_key(id: &ProfileId) -> String {
+    format!("profile-meta:{id}")
+}
+
+/// Gets a profile's metadata, going through the Redis cache.
+///
+/// Use [`get_uncached`] right after modifying the profile, since the cache
+/// is only invalidated once the change is announced.
 pub async fn get(state: &AppState, id: &ProfileId) -> AppResult<Option<ProfileMetadata>> {
-    let profile = sqlx::query!(
+    let ttl = state.profile_config.cache_ttl;
+
+    if ttl.is_zero() {
+        return get_uncached(state, id).await;
+    }
+
+    let mut redis = state.redis.clone();
+
+    match redis.get::<_, Option<String>>(cache_key(id)).await {
+        Ok(Some(json)) => match serde_json::from_str::<CachedMetadata>(&json) {
+            Ok(cached) => {
+                let mut metadata = cached.metadata;
+                cached.owner.restore(&mut metadata.owner);
+                for (user, hidden) in metadata.collaborators.iter_mut().zip(cached.collaborators) {
+                    hidden.restore(user);
+                }
+
+                return Ok(Some(metadata));
+            }
+            Err(err) => warn!("failed to deserialize cached metadata of profile {id}: {err}"),
+        },
+        Ok(None) => (),
+        Err(err) => warn!("failed to read cached metadata of profile {id}: {err}"),
+    }
+
+    let Some(metadata) = get_uncached(state, id).await? else {
+        return Ok(None);
+    };
+
+    let cached = CachedMetadata {
+        owner: HiddenUserFields::new(&metadata.owner),
+        collaborators: metadata
+            .collaborators
+            .iter()
+            .map(HiddenUserFields::new)
+            .collect(),
+        metadata,
+    };
+
+    match serde_json::to_string(&cached) {
+        Ok(json) => {
+            if let Err(err) = redis
+                .set_ex::<_, _, ()>(cache_key(id), json, ttl.as_secs())
+                .await
+            {
+                warn!("failed to cache metadata of profile {id}: {err}");
+            }
+        }
+        Err(err) => warn!("failed to serialize metadata of profile {id}: {err}"),
+    }
+
+    Ok(Some(cached.metadata))
+}
+
+/// Gets a profile's metadata directly from the database.
+pub async fn get_uncached(state: &AppState, id: &ProfileId) -> AppResult<Option<ProfileMetadata>> {
+    let profiles = get_many(state, std::slice::from_ref(id)).await?;
+
+    Ok(profiles.into_iter().next())
+}
+
+/// Gets the metadata of several profiles at once, bypassing the cache.
+///
+/// Profiles that don't exist are left out of the result.
+pub async fn get_many(state: &AppState, ids: &[ProfileId]) -> AppResult<Vec<ProfileMetadata>> {
+    let mut profiles = sqlx::query!(
         r#"SELECT
+            p.short_id AS "short_id: ProfileId",
             p.name,
             p.community,
             p.mods AS "mods: sqlx::types::Json<Vec<ProfileMod>>",
             p.created_at,
             p.updated_at,
+            p.visibility AS "visibility: Visibility",
+            p.downloads,
+            p.config_file_count,
+            p.config_size_bytes,
             u.id AS "owner_id",
             u.name AS "owner_name",
             u.display_name AS "owner_display_name",
             u.avatar,
-            u.discord_id
+            u.discord_id,
+            u.public_flags,
+            u.banner,
+            u.avatar_decoration,
+            u.public_id,
+            u.is_admin,
+            ub.public_id AS "updated_by?"
         FROM profiles p
         JOIN users u ON u.id = p.owner_id
-        WHERE p.short_id = $1"#,
-        &id.to_string()
+        LEFT JOIN users ub ON ub.id = p.updated_by
+        WHERE
+            p.short_id = ANY($1) AND
+            p.deleted_at IS NULL"#,
+        ids as &[ProfileId]
     )
     .map(|record| ProfileMetadata {
-        short_id: id.clone(),
+        short_id: record.short_id,
         created_at: record.created_at,
         updated_at: record.updated_at,
+        visibility: record.visibility,
+        downloads: record.downloads,
         owner: User {
             id: record.owner_id,
             name: record.owner_name,
             display_name: record.owner_display_name,
             avatar: record.avatar,
             discord_id: record.discord_id,
+            public_flags: record.public_flags,
+            banner: record.banner,
+            avatar_decoration: record.avatar_decoration,
+            public_id: record.public_id,
+            is_admin: record.is_admin,
+        },
+        collaborators: Vec::new(),
+        updated_by: record.updated_by.map(ShortUuid),
+        config_files: ConfigFiles {
+            count: record.config_file_count,
+            size_bytes: record.config_size_bytes,
         },
         manifest: ProfileManifest {
             profile_name: record.name,
@@ -181,21 +521,424 @@ pub async fn get(state: &AppState, id: &ProfileId) -> AppResult<Option<ProfileMe
             mods: record.mods.0,
         },
     })
-    .fetch_optional(&state.db)
+    .fetch_all(&state.db)
     .await?;
 
-    Ok(profile)
+    if profiles.is_empty() {
+        return Ok(profiles);
+    }
+
+    let collaborators = sqlx::query!(
+        r#"SELECT
+            p.short_id AS "short_id: ProfileId",
+            u.id,
+            u.name,
+            u.display_name,
+            u.discord_id,
+            u.avatar,
+            u.public_flags,
+            u.banner,
+            u.avatar_decoration,
+            u.public_id,
+            u.is_admin
+        FROM profile_collaborators c
+        JOIN profiles p ON p.id = c.profile_id
+        JOIN users u ON u.id = c.user_id
+        WHERE p.short_id = ANY($1)
+        ORDER BY c.created_at"#,
+        ids as &[ProfileId]
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    for record in collaborators {
+        if let Some(profile) = profiles
+            .iter_mut()
+            .find(|profile| profile.short_id == record.short_id)
+        {
+            profile.collaborators.push(User {
+                id: record.id,
+                name: record.name,
+                display_name: record.display_name,
+                discord_id: record.discord_id,
+                avatar: record.avatar,
+                public_flags: record.public_flags,
+                banner: record.banner,
+                avatar_decoration: record.avatar_decoration,
+                public_id: record.public_id,
+                is_admin: record.is_admin,
+            });
+        }
+    }
+
+    Ok(profiles)
 }
 
-pub async fn exists(state: &AppState, id: &ProfileId) -> AppResult<bool> {
-    let result = sqlx::query!(
-        "SELECT COUNT(1) FROM profiles p WHERE p.short_id = $1",
-        &id.to_string()
+/// The total size of the archives of a profile's owner, excluding the profile itself.
+///
+/// If the profile doesn't exist yet, `user_id` is assumed to become its owner.
+pub async fn storage_used(state: &AppState, id: &ProfileId, user_id: i32) -> AppResult<u64> {
+    let used = sqlx::query_scalar!(
+        r#"SELECT COALESCE(SUM(size_bytes), 0)::BIGINT AS "used!"
+        FROM profiles
+        WHERE
+            owner_id = COALESCE((SELECT owner_id FROM profiles WHERE short_id = $1), $2) AND
+            short_id != $1"#,
+        &*id.as_str(),
+        user_id
     )
     .fetch_one(&state.db)
     .await?;
 
-    Ok(result.count.is_some_and(|c| c > 0))
+    Ok(used as u64)
+}
+
+/// A lightweight version of [`ProfileMetadata`] used in listings.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileSummary {
+    #[serde(rename = "id")]
+    pub short_id: ProfileId,
+    pub name: String,
+    pub community: Option<String>,
+    pub mod_count: i32,
+    #[serde(with = "crate::rfc3339")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::rfc3339")]
+    pub updated_at: DateTime<Utc>,
+    pub owner: User,
+}
+
+/// Position in the recent profiles feed, see [`recent`].
+///
+/// Encoded as an opaque string so clients don't depend on its contents.
+#[derive(Debug, Clone)]
+pub struct FeedCursor {
+    updated_at: DateTime<Utc>,
+    short_id: String,
+}
+
+impl Display for FeedCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let raw = format!("{}.{}", self.updated_at.timestamp_micros(), self.short_id);
+        f.write_str(&BASE64_URL_SAFE_NO_PAD.encode(raw))
+    }
+}
+
+impl FromStr for FeedCursor {
+    type Err = AppError;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppError::bad_request("Invalid cursor.");
+
+        let raw = BASE64_URL_SAFE_NO_PAD
+            .decode(str)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+
+        let (micros, short_id) = raw.split_once('.').ok_or_else(invalid)?;
+        let updated_at = micros
+            .parse()
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(invalid)?;
+
+        Ok(FeedCursor {
+            updated_at,
+            short_id: short_id.to_owned(),
+        })
+    }
+}
+
+/// Lists public profiles, most recently updated first.
+///
+/// Returns the cursor of the next page, if there might be one.
+pub async fn recent(
+    state: &AppState,
+    limit: i64,
+    cursor: Option<FeedCursor>,
+) -> AppResult<(Vec<ProfileSummary>, Option<FeedCursor>)> {
+    let (cursor_updated_at, cursor_short_id) = cursor
+        .map(|cursor| (cursor.updated_at, cursor.short_id))
+        .unzip();
+
+    let profiles = sqlx::query!(
+        r#"SELECT
+            p.short_id AS "short_id: ProfileId",
+            p.name,
+            p.community,
+            JSONB_ARRAY_LENGTH(p.mods) AS "mod_count!",
+            p.created_at,
+            p.updated_at,
+            u.id AS "owner_id",
+            u.name AS "owner_name",
+            u.display_name AS "owner_display_name",
+            u.avatar,
+            u.discord_id,
+            u.public_flags,
+            u.banner,
+            u.avatar_decoration,
+            u.public_id,
+            u.is_admin
+        FROM profiles p
+        JOIN users u ON u.id = p.owner_id
+        WHERE
+            p.visibility = 'public' AND
+            p.deleted_at IS NULL AND
+            ($2::TIMESTAMPTZ IS NULL OR (p.updated_at, p.short_id) < ($2, $3))
+        ORDER BY
+            p.updated_at DESC,
+            p.short_id DESC
+        LIMIT $1"#,
+        limit,
+        cursor_updated_at,
+        cursor_short_id
+    )
+    .map(|record| ProfileSummary {
+        short_id: record.short_id,
+        name: record.name,
+        community: record.community,
+        mod_count: record.mod_count,
+        created_at: record.created_at,
+        updated_at: record.updated_at,
+        owner: User {
+            id: record.owner_id,
+            name: record.owner_name,
+            display_name: record.owner_display_name,
+            avatar: record.avatar,
+            discord_id: record.discord_id,
+            public_flags: record.public_flags,
+            banner: record.banner,
+            avatar_decoration: record.avatar_decoration,
+            public_id: record.public_id,
+            is_admin: record.is_admin,
+        },
+    })
+    .fetch_all(&state.db)
+    .await?;
+
+    // a short page means there's nothing left
+    let next = match profiles.last() {
+        Some(last) if profiles.len() as i64 == limit => Some(FeedCursor {
+            updated_at: last.updated_at,
+            short_id: last.short_id.to_string(),
+        }),
+        _ => None,
+    };
+
+    Ok((profiles, next))
+}
+
+/// Time span over which downloads are counted for [`trending`].
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub enum TrendingWindow {
+    #[default]
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+}
+
+impl TrendingWindow {
+    /// The longest window, download events older than this are pruned.
+    const MAX: Duration = TrendingWindow::Week.duration();
+
+    const fn duration(self) -> Duration {
+        match self {
+            TrendingWindow::Day => Duration::from_secs(24 * 60 * 60),
+            TrendingWindow::Week => Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TrendingProfile {
+    #[serde(flatten)]
+    pub profile: ProfileSummary,
+    /// Downloads within the requested window.
+    pub recent_downloads: i64,
+}
+
+/// Lists public profiles with the most downloads within `window`.
+pub async fn trending(
+    state: &AppState,
+    window: TrendingWindow,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<TrendingProfile>> {
+    let since = Utc::now() - window.duration();
+
+    let profiles = sqlx::query!(
+        r#"WITH counts AS (
+            SELECT profile_id, COUNT(*) AS downloads
+            FROM profile_downloads
+            WHERE downloaded_at > $1
+            GROUP BY profile_id
+        )
+        SELECT
+            p.short_id AS "short_id: ProfileId",
+            p.name,
+            p.community,
+            JSONB_ARRAY_LENGTH(p.mods) AS "mod_count!",
+            p.created_at,
+            p.updated_at,
+            c.downloads AS "downloads!",
+            u.id AS "owner_id",
+            u.name AS "owner_name",
+            u.display_name AS "owner_display_name",
+            u.avatar,
+            u.discord_id,
+            u.public_flags,
+            u.banner,
+            u.avatar_decoration,
+            u.public_id,
+            u.is_admin
+        FROM counts c
+        JOIN profiles p ON p.id = c.profile_id
+        JOIN users u ON u.id = p.owner_id
+        WHERE
+            p.visibility = 'public' AND
+            p.deleted_at IS NULL
+        ORDER BY
+            c.downloads DESC,
+            p.updated_at DESC
+        LIMIT $2
+        OFFSET $3"#,
+        since,
+        limit,
+        offset
+    )
+    .map(|record| TrendingProfile {
+        profile: ProfileSummary {
+            short_id: record.short_id,
+            name: record.name,
+            community: record.community,
+            mod_count: record.mod_count,
+            created_at: record.created_at,
+            updated_at: record.updated_at,
+            owner: User {
+                id: record.owner_id,
+                name: record.owner_name,
+                display_name: record.owner_display_name,
+                avatar: record.avatar,
+                discord_id: record.discord_id,
+                public_flags: record.public_flags,
+                banner: record.banner,
+                avatar_decoration: record.avatar_decoration,
+                public_id: record.public_id,
+                is_admin: record.is_admin,
+            },
+        },
+        recent_downloads: record.downloads,
+    })
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(profiles)
+}
+
+/// Searches public profiles by name and community.
+///
+/// Exact name matches are ranked first, then prefix matches, then the most recently updated.
+pub async fn search(
+    state: &AppState,
+    query: &str,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<ProfileSummary>> {
+    let escaped = query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    let profiles = sqlx::query!(
+        r#"SELECT
+            p.short_id AS "short_id: ProfileId",
+            p.name,
+            p.community,
+            JSONB_ARRAY_LENGTH(p.mods) AS "mod_count!",
+            p.created_at,
+            p.updated_at,
+            u.id AS "owner_id",
+            u.name AS "owner_name",
+            u.display_name AS "owner_display_name",
+            u.avatar,
+            u.discord_id,
+            u.public_flags,
+            u.banner,
+            u.avatar_decoration,
+            u.public_id,
+            u.is_admin
+        FROM profiles p
+        JOIN users u ON u.id = p.owner_id
+        WHERE
+            p.visibility = 'public' AND
+            p.deleted_at IS NULL AND
+            (p.name ILIKE '%' || $1 || '%' OR p.community ILIKE '%' || $1 || '%')
+        ORDER BY
+            LOWER(p.name) = LOWER($2) DESC,
+            p.name ILIKE $1 || '%' DESC,
+            p.updated_at DESC
+        LIMIT $3
+        OFFSET $4"#,
+        escaped,
+        query,
+        limit,
+        offset
+    )
+    .map(|record| ProfileSummary {
+        short_id: record.short_id,
+        name: record.name,
+        community: record.community,
+        mod_count: record.mod_count,
+        created_at: record.created_at,
+        updated_at: record.updated_at,
+        owner: User {
+            id: record.owner_id,
+            name: record.owner_name,
+            display_name: record.owner_display_name,
+            avatar: record.avatar,
+            discord_id: record.discord_id,
+            public_flags: record.public_flags,
+            banner: record.banner,
+            avatar_decoration: record.avatar_decoration,
+            public_id: record.public_id,
+            is_admin: record.is_admin,
+        },
+    })
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(profiles)
+}
+
+pub async fn collaborators(state: &AppState, id: &ProfileId) -> AppResult<Vec<User>> {
+    let users = sqlx::query_as!(
+        User,
+        "SELECT
+            u.id,
+            u.name,
+            u.display_name,
+            u.discord_id,
+            u.avatar,
+            u.public_flags,
+            u.banner,
+            u.avatar_decoration,
+            u.public_id,
+            u.is_admin
+        FROM profile_collaborators c
+        JOIN profiles p ON p.id = c.profile_id
+        JOIN users u ON u.id = c.user_id
+        WHERE p.short_id = $1
+        ORDER BY c.created_at",
+        &*id.as_str()
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(users)
 }
 
 #[derive(Debug, Deserialize)]
@@ -204,13 +947,45 @@ struct CodeResponse {
 }
 
 pub async fn upload(state: &AppState, body: Bytes) -> AppResult<Uuid> {
+    upload_with(state, || async {
+        Ok((body.len() as u64, body.clone().into()))
+    })
+    .await
+}
+
+/// Like [`upload`], but streams the archive from disk instead of holding it in memory.
+pub async fn upload_file(state: &AppState, path: &Path) -> AppResult<Uuid> {
+    upload_with(state, || async {
+        let file = tokio::fs::File::open(path)
+            .await
+            .context("failed to open archive")?;
+        let size = file
+            .metadata()
+            .await
+            .context("failed to open archive")?
+            .len();
+
+        Ok((size, reqwest::Body::wrap_stream(ReaderStream::new(file))))
+    })
+    .await
+}
+
+/// Since a body can only be sent once, `body` is called to create a new one for each attempt.
+async fn upload_with<F, Fut>(state: &AppState, mut body: F) -> AppResult<Uuid>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = AppResult<(u64, reqwest::Body)>>,
+{
     let mut retries = 0;
 
     loop {
+        let (size, body) = body().await?;
+
         let response = state
             .http
-            .post("https://thunderstore.io/api/experimental/legacyprofile/create/")
-            .body(body.clone())
+            .post(thunderstore::api_url(state, "legacyprofile/create/"))
+            .header(CONTENT_LENGTH, size)
+            .body(body)
             .send()
             .await?;
 
@@ -244,12 +1019,207 @@ pub async fn upload(state: &AppState, body: Bytes) -> AppResult<Uuid> {
     }
 }
 
-pub fn storage_key(id: &ProfileId) -> String {
-    format!(
-        "profile/{}.zip",
-        match id {
-            ProfileId::Legacy(short_uuid) => &short_uuid.0 as &dyn Display,
-            ProfileId::Short(short) => short,
+fn storage_id(id: &ProfileId) -> &dyn Display {
+    match id {
+        ProfileId::Legacy(short_uuid) => &short_uuid.0,
+        ProfileId::Short(short) => short,
+    }
+}
+
+/// Periodically purges profiles whose restore window has passed, as well as
+/// download events that no longer count towards trending profiles.
+pub async fn run_purge_task(state: AppState) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match purge_deleted(&state).await {
+            Ok(0) => (),
+            Ok(count) => info!("purged {count} deleted profiles"),
+            Err(err) => error!("failed to purge deleted profiles: {err:#}"),
+        }
+
+        if let Err(err) = prune_download_events(&state).await {
+            error!("failed to prune download events: {err:#}");
+        }
+    }
+}
+
+/// Permanently deletes profiles that were deleted longer ago than the restore window,
+/// including their archives in storage.
+pub async fn purge_deleted(state: &AppState) -> anyhow::Result<usize> {
+    let window = PgInterval::try_from(state.profile_config.restore_window)
+        .map_err(|err| anyhow::anyhow!("invalid restore window: {err}"))?;
+
+    let mut tx = state.db.begin().await?;
+
+    let version_keys = sqlx::query_scalar!(
+        "SELECT v.storage_key
+        FROM profile_versions v
+        JOIN profiles p ON p.id = v.profile_id
+        WHERE p.deleted_at < NOW() - $1::INTERVAL",
+        window
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let ids = sqlx::query_scalar!(
+        r#"DELETE FROM profiles
+        WHERE deleted_at < NOW() - $1::INTERVAL
+        RETURNING short_id AS "short_id: ProfileId""#,
+        window
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    for key in ids.iter().map(storage_key).chain(version_keys) {
+        if let Err(err) = state.storage.delete(&key).await {
+            error!("failed to delete archive at {key}: {err:#}");
         }
+    }
+
+    Ok(ids.len())
+}
+
+/// Deletes download events that are too old to count towards any [`TrendingWindow`].
+async fn prune_download_events(state: &AppState) -> anyhow::Result<()> {
+    let cutoff = Utc::now() - TrendingWindow::MAX;
+
+    sqlx::query!(
+        "DELETE FROM profile_downloads WHERE downloaded_at < $1",
+        cutoff
     )
+    .execute(&state.db)
+    .await?;
+
+    Ok(())
+}
+
+/// Where the archive of a profile lives if the `storage_key` column is NULL,
+/// which is the case for profiles last uploaded before archives got unique keys.
+pub fn storage_key(id: &ProfileId) -> String {
+    format!("profile/{}.zip", storage_id(id))
+}
+
+/// The key of a profile's current archive, given its `storage_key` column.
+pub fn current_storage_key(id: &ProfileId, stored: Option<String>) -> String {
+    stored.unwrap_or_else(|| storage_key(id))
+}
+
+/// A fresh key for an uploaded archive.
+///
+/// Every upload gets its own key, so concurrent uploads never overwrite each other
+/// and a failed one can be deleted without touching the current archive.
+pub fn new_storage_key() -> String {
+    format!("archives/{}.zip", Uuid::new_v4())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn metadata() -> ProfileMetadata {
+        let owner = User {
+            id: 1,
+            discord_id: "123456789012345678".to_owned(),
+            name: "owner".to_owned(),
+            display_name: "Owner".to_owned(),
+            avatar: None,
+            public_flags: 0,
+            banner: None,
+            avatar_decoration: None,
+            public_id: Uuid::now_v7(),
+            is_admin: true,
+        };
+
+        ProfileMetadata {
+            short_id: ProfileId::Short("ABC123".to_owned()),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            visibility: Visibility::Unlisted,
+            downloads: 3,
+            updated_by: Some(owner.public_id.into()),
+            collaborators: vec![User {
+                id: 2,
+                name: "collaborator".to_owned(),
+                public_id: Uuid::now_v7(),
+                is_admin: false,
+                ..owner.clone()
+            }],
+            owner,
+            config_files: ConfigFiles {
+                count: 2,
+                size_bytes: 1024,
+            },
+            manifest: ProfileManifest {
+                profile_name: "Modded".to_owned(),
+                community: Some("lethal-company".to_owned()),
+                mods: vec![ProfileMod {
+                    name: "BepInEx-BepInExPack".to_owned(),
+                    enabled: true,
+                    version: ModVersion {
+                        major: 5,
+                        minor: 4,
+                        patch: 2100,
+                    },
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn uploads_never_reuse_a_key() {
+        let id = ProfileId::Short("ABC123".to_owned());
+        let key = new_storage_key();
+
+        // a failed upload deletes its own key, which mustn't be any other archive's
+        assert_ne!(key, new_storage_key());
+        assert_ne!(key, storage_key(&id));
+
+        assert_eq!(current_storage_key(&id, None), storage_key(&id));
+        assert_eq!(current_storage_key(&id, Some(key.clone())), key);
+    }
+
+    #[test]
+    fn cached_metadata_keeps_hidden_fields() {
+        let metadata = metadata();
+        let cached = CachedMetadata {
+            owner: HiddenUserFields::new(&metadata.owner),
+            collaborators: metadata
+                .collaborators
+                .iter()
+                .map(HiddenUserFields::new)
+                .collect(),
+            metadata,
+        };
+
+        let json = serde_json::to_string(&cached).unwrap();
+        let parsed: CachedMetadata = serde_json::from_str(&json).unwrap();
+
+        let mut owner = parsed.metadata.owner;
+        assert_eq!((owner.id, owner.is_admin), (0, false));
+
+        parsed.owner.restore(&mut owner);
+        assert_eq!((owner.id, owner.is_admin), (1, true));
+        assert_eq!(parsed.collaborators[0].id, 2);
+    }
+
+    #[test]
+    fn metadata_json_round_trip() {
+        let metadata = metadata();
+
+        let json = serde_json::to_string(&metadata).unwrap();
+        let parsed: ProfileMetadata = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parsed.owner.public_id, metadata.owner.public_id);
+        assert_eq!(parsed.collaborators.len(), 1);
+        assert_eq!(
+            parsed.manifest.mods[0].version,
+            metadata.manifest.mods[0].version
+        );
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+    }
 }
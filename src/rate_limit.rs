@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use redis::{ExistenceCheck, SetExpiry, SetOptions};
+use tracing::warn;
+
+use crate::prelude::*;
+
+/// Allows at most `limit` calls with the same `key` per `window`, counted in Redis.
+///
+/// If Redis is unavailable, the call is allowed rather than failing the request.
+pub async fn check(state: &AppState, key: &str, limit: u32, window: Duration) -> AppResult<()> {
+    let mut redis = state.redis.clone();
+    let key = format!("rate-limit:{key}");
+
+    // the window starts with the first call, and the key can't end up without an expiry
+    // since both commands run in the same transaction
+    let result: redis::RedisResult<(u32,)> = redis::pipe()
+        .atomic()
+        .set_options(
+            &key,
+            0,
+            SetOptions::default()
+                .conditional_set(ExistenceCheck::NX)
+                .with_expiration(SetExpiry::EX(window.as_secs())),
+        )
+        .ignore()
+        .incr(&key, 1)
+        .query_async(&mut redis)
+        .await;
+
+    match result {
+        Ok((count,)) if count > limit => Err(AppError::too_many_requests(format!(
+            "Too many requests. Try again in {} minutes.",
+            window.as_secs().div_ceil(60)
+        ))),
+        Ok(_) => Ok(()),
+        Err(err) => {
+            warn!("failed to check rate limit for {key}: {err}");
+            Ok(())
+        }
+    }
+}
@@ -1,12 +1,24 @@
 use std::borrow::Cow;
 
-use axum::response::Html;
+use axum::response::{Html, IntoResponse, Response};
+use tracing::warn;
+use url::{form_urlencoded, Url};
 
+use crate::prelude::*;
+
+/// Schemes that are safe to redirect to.
+const ALLOWED_SCHEMES: &[&str] = &["gale", "http", "https"];
+
+/// Renders `assets/redirect.html`, which opens `url` and shows an embed with
+/// the given title, description and image when linked to.
+///
+/// All values are escaped before being inserted into the page.
 pub struct RedirectBuilder<'a> {
     title: Option<Cow<'a, str>>,
     description: Option<Cow<'a, str>>,
     image: Option<Cow<'a, str>>,
     url: Cow<'a, str>,
+    query: Vec<(&'a str, Cow<'a, str>)>,
 }
 
 impl<'a> RedirectBuilder<'a> {
@@ -16,6 +28,7 @@ impl<'a> RedirectBuilder<'a> {
             description: None,
             image: None,
             url: url.into(),
+            query: Vec::new(),
         }
     }
 
@@ -34,19 +47,128 @@ impl<'a> RedirectBuilder<'a> {
         self
     }
 
+    /// Appends a URL-encoded query parameter to the redirect URL.
+    pub fn query(mut self, name: &'a str, value: impl Into<Cow<'a, str>>) -> Self {
+        self.query.push((name, value.into()));
+        self
+    }
+
     pub fn build(self) -> Html<String> {
-        let mut html = include_str!("../assets/redirect.html").replace("%REDIRECT_URL%", &self.url);
+        let url = if self.query.is_empty() {
+            self.url
+        } else {
+            let query = form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(&self.query)
+                .finish();
 
-        if let Some(title) = self.title {
-            html = html.replace("%TITLE%", &title);
-        }
-        if let Some(description) = self.description {
-            html = html.replace("%DESCRIPTION%", &description);
+            format!("{}?{query}", self.url).into()
+        };
+
+        let html = include_str!("../assets/redirect.html")
+            .replace("%REDIRECT_URL%", &escape(&sanitize_url(&url)))
+            .replace(
+                "%TITLE%",
+                &escape(self.title.as_deref().unwrap_or_default()),
+            )
+            .replace(
+                "%DESCRIPTION%",
+                &escape(self.description.as_deref().unwrap_or_default()),
+            )
+            .replace(
+                "%IMAGE%",
+                &escape(self.image.as_deref().unwrap_or_default()),
+            );
+
+        Html(html)
+    }
+}
+
+/// Normalizes the URL and replaces it with `#` if it's invalid or uses a scheme
+/// like `javascript:`.
+fn sanitize_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) if ALLOWED_SCHEMES.contains(&url.scheme()) => url.into(),
+        _ => {
+            warn!("refusing to redirect to {url:?}");
+            "#".to_owned()
         }
-        if let Some(image) = self.image {
-            html = html.replace("%IMAGE%", &image);
+    }
+}
+
+/// Escapes text for use in HTML, both as element content and in quoted attributes.
+fn escape(str: &str) -> Cow<'_, str> {
+    if !str.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(str);
+    }
+
+    let mut escaped = String::with_capacity(str.len());
+    for char in str.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            char => escaped.push(char),
         }
+    }
 
-        Html(html)
+    Cow::Owned(escaped)
+}
+
+/// Renders an error as an HTML page, for endpoints that are opened in a browser
+/// instead of being called by a client.
+///
+/// If `retry_url` is given, the page links to it so the user can start over.
+pub fn error_page(error: AppError, title: &str, retry_url: Option<&str>) -> Response {
+    error.log();
+
+    let retry_link = match retry_url {
+        Some(url) => format!("<a id=\"link\" href=\"{}\">Try again</a>", escape(url)),
+        None => String::new(),
+    };
+
+    let html = include_str!("../assets/error.html")
+        .replace("%TITLE%", &escape(title))
+        .replace("%MESSAGE%", &escape(&error.to_string()))
+        .replace("%RETRY_LINK%", &retry_link);
+
+    (error.status(), Html(html)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &str = "\"><script>alert(1)</script>";
+
+    #[test]
+    fn escape_special_characters() {
+        assert_eq!(
+            escape(PAYLOAD),
+            "&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+        assert_eq!(escape("it's & fine"), "it&#39;s &amp; fine");
+        assert!(matches!(escape("plain"), Cow::Borrowed("plain")));
+    }
+
+    #[test]
+    fn build_escapes_values() {
+        let Html(html) = RedirectBuilder::new(format!("gale://profile/sync/clone/{PAYLOAD}"))
+            .title(PAYLOAD)
+            .description(PAYLOAD)
+            .query("id", PAYLOAD)
+            .build();
+
+        assert!(!html.contains("<script>alert"));
+        assert!(!html.contains(PAYLOAD));
+    }
+
+    #[test]
+    fn build_rejects_javascript_urls() {
+        let Html(html) = RedirectBuilder::new("javascript:alert(1)").build();
+
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("href=\"#\""));
     }
 }
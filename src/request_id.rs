@@ -0,0 +1,46 @@
+use std::sync::Arc;
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use http::{HeaderName, HeaderValue};
+use tracing::Instrument;
+use uuid::Uuid;
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: Arc<str>;
+}
+
+/// Returns the id of the request currently being handled, if any.
+pub fn current() -> Option<Arc<str>> {
+    REQUEST_ID.try_with(Arc::clone).ok()
+}
+
+/// Assigns each request an id, or reuses the one sent by the client in `X-Request-Id`.
+///
+/// The id is attached to the tracing span of the request and echoed back in the response,
+/// so that server logs can be matched up with what the client received.
+pub async fn middleware(request: Request, next: Next) -> Response {
+    let id: Arc<str> = request
+        .headers()
+        .get(&HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_LENGTH)
+        .map(Into::into)
+        .unwrap_or_else(|| Uuid::new_v4().to_string().into());
+
+    let span = tracing::info_span!("request", id = %id);
+
+    let mut response = REQUEST_ID
+        .scope(id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(HEADER, value);
+    }
+
+    response
+}
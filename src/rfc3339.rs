@@ -0,0 +1,59 @@
+//! Serializes timestamps as RFC 3339 in UTC with a `Z` suffix and a fixed number of
+//! fractional digits, for example `2025-05-14T19:04:00.753826Z`.
+//!
+//! chrono's default format drops trailing zeros from the fraction, which some clients
+//! fail to parse. Use with `#[serde(with = "crate::rfc3339")]`.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(value: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_rfc3339_opts(SecondsFormat::Micros, true))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    let str = String::deserialize(deserializer)?;
+
+    DateTime::parse_from_rfc3339(&str)
+        .map(|value| value.with_timezone(&Utc))
+        .map_err(D::Error::custom)
+}
+
+/// Like the parent module, but for optional timestamps.
+pub mod option {
+    use chrono::{DateTime, Utc};
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Timestamp(#[serde(with = "super")] DateTime<Utc>);
+
+    #[test]
+    fn round_trip_keeps_trailing_zeros() {
+        // 500ms, which chrono would shorten to `.5`
+        let value = Utc.timestamp_opt(1_747_249_440, 500_000_000).unwrap();
+
+        let json = serde_json::to_string(&Timestamp(value)).unwrap();
+        assert_eq!(json, "\"2025-05-14T19:04:00.500000Z\"");
+
+        let Timestamp(parsed) = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, value);
+    }
+}
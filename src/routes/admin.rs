@@ -0,0 +1,135 @@
+use axum::{
+    extract::State,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    auth::AdminUser,
+    migrate::JobStatus,
+    prelude::*,
+    profile::{self, ProfileId},
+    routes::Path,
+    webhook,
+};
+
+const MAX_REASON_LENGTH: usize = 1000;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/migrate", post(start_migration))
+        .route("/migrate/{job_id}", get(migration_status))
+        .route("/profile/{id}", delete(take_down_profile))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StartMigrationResponse {
+    job_id: Uuid,
+}
+
+/// Uploads all profiles without a Thunderstore code in the background.
+async fn start_migration(
+    AdminUser(_): AdminUser,
+    State(state): State<AppState>,
+) -> Json<StartMigrationResponse> {
+    let job_id = state.migrations.start(state.clone());
+
+    Json(StartMigrationResponse { job_id })
+}
+
+async fn migration_status(
+    AdminUser(_): AdminUser,
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+) -> AppResult<Json<JobStatus>> {
+    state
+        .migrations
+        .get(&job_id)
+        .map(Json)
+        .ok_or(AppError::NotFound)
+}
+
+#[derive(Debug, Deserialize)]
+struct TakeDownRequest {
+    reason: String,
+}
+
+/// Permanently deletes a profile regardless of its owner, logging the reason.
+async fn take_down_profile(
+    AdminUser(admin): AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+    Json(request): Json<TakeDownRequest>,
+) -> AppResult<StatusCode> {
+    let reason = request.reason.trim();
+
+    if reason.is_empty() {
+        return Err(AppError::bad_request("A reason is required."));
+    }
+
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(AppError::bad_request(format!(
+            "Reason is too long. The maximum is {MAX_REASON_LENGTH} characters."
+        )));
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let version_keys = sqlx::query_scalar!(
+        "SELECT v.storage_key
+        FROM profile_versions v
+        JOIN profiles p ON p.id = v.profile_id
+        WHERE p.short_id = $1",
+        &*id.as_str()
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    // soft-deleted profiles are included, since they can still be restored
+    let owner = sqlx::query!(
+        "DELETE FROM profiles p
+        USING users u
+        WHERE
+            p.short_id = $1 AND
+            u.id = p.owner_id
+        RETURNING p.owner_id, u.discord_id",
+        &*id.as_str()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    sqlx::query!(
+        "INSERT INTO moderation_log (admin_id, action, profile_id, owner_id, reason)
+        VALUES ($1, 'take_down', $2, $3, $4)",
+        admin.id,
+        &*id.as_str(),
+        owner.owner_id,
+        reason
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    info!("profile {id} was taken down by {}: {reason}", admin.name);
+
+    for key in std::iter::once(profile::storage_key(&id)).chain(version_keys) {
+        if let Err(err) = state.storage.delete(&key).await {
+            error!("failed to delete archive at {key}: {err:#}");
+        }
+    }
+
+    state
+        .sockets
+        .notify_profile_deleted(&id, &owner.discord_id)
+        .await;
+    webhook::notify_profile_deleted(&state, owner.owner_id, &id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,7 +1,9 @@
+use std::time::{Duration, SystemTime};
+
 use anyhow::Context;
 use axum::{
     extract::{Query, State},
-    response::{Html, Redirect},
+    response::{Html, IntoResponse, Redirect, Response},
     routing::{get, post},
     Json, Router,
 };
@@ -9,15 +11,19 @@ use axum_extra::extract::{
     cookie::{Cookie, SameSite},
     CookieJar,
 };
-use http::StatusCode;
+use base64::prelude::*;
+use http::{HeaderMap, StatusCode};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
 use url::Url;
 use uuid::Uuid;
 
 use crate::{
-    auth::{self, User},
+    auth::{self, token::JwtClaims, User},
     prelude::*,
-    redirect::RedirectBuilder,
+    redirect::{self, RedirectBuilder},
 };
 
 pub fn routes() -> Router<AppState> {
@@ -25,37 +31,74 @@ pub fn routes() -> Router<AppState> {
         .route("/login", get(login))
         .route("/callback", get(oauth_callback))
         .route("/token", post(grant_token))
+        .route("/introspect", post(introspect))
 }
 
-const DISCORD_API_ENDPOINT: &str = "https://discord.com/api/v10";
-
-#[cfg(debug_assertions)]
-const SERVER_REDIRECT_URI: &str = "http://localhost:8080/api/auth/callback";
+/// How long a user has to complete the login after visiting `/auth/login`.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
 
-#[cfg(not(debug_assertions))]
-const SERVER_REDIRECT_URI: &str = "http://gale.kesomannen.com/api/auth/callback";
+const DISCORD_API_ENDPOINT: &str = "https://discord.com/api/v10";
 
 async fn login(
     State(state): State<AppState>,
     mut cookies: CookieJar,
 ) -> AppResult<(CookieJar, Redirect)> {
-    let oauth_state = Uuid::new_v4().to_string();
+    // v7 ids contain a timestamp, which lets the callback check the age of the login
+    let oauth_state = Uuid::now_v7().to_string();
+    let code_verifier = BASE64_URL_SAFE_NO_PAD.encode(rand::rng().random::<[u8; 32]>());
+    let code_challenge = BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
 
     let mut url = Url::parse(&format!("{DISCORD_API_ENDPOINT}/oauth2/authorize")).unwrap();
     url.query_pairs_mut()
         .append_pair("response_type", "code")
         .append_pair("client_id", &state.discord_client_id)
-        .append_pair("scope", "identify")
-        .append_pair("redirect_uri", SERVER_REDIRECT_URI)
-        .append_pair("state", &oauth_state);
+        .append_pair("scope", &state.discord_oauth_scopes)
+        .append_pair("redirect_uri", &state.oauth_redirect_uri)
+        .append_pair("state", &oauth_state)
+        .append_pair("code_challenge", &code_challenge)
+        .append_pair("code_challenge_method", "S256");
 
-    let mut cookie = Cookie::new("state", oauth_state);
+    cookies = cookies
+        .add(oauth_cookie("state", oauth_state))
+        .add(oauth_cookie("code_verifier", code_verifier));
+
+    Ok((cookies, Redirect::to(url.as_str())))
+}
+
+fn oauth_cookie(name: &'static str, value: String) -> Cookie<'static> {
+    let mut cookie = Cookie::new(name, value);
     cookie.set_same_site(SameSite::Lax);
     cookie.set_secure(true);
     cookie.set_http_only(true);
-    cookies = cookies.add(cookie);
+    cookie.set_max_age(time::Duration::try_from(LOGIN_TIMEOUT).ok());
+    cookie
+}
 
-    Ok((cookies, Redirect::to(url.as_str())))
+/// Checks that the login started in [`login`] hasn't timed out, in case the browser
+/// kept the cookie longer than it should.
+fn check_login_age(oauth_state: &str) -> AppResult<()> {
+    let started_at = Uuid::parse_str(oauth_state)
+        .ok()
+        .and_then(|uuid| uuid.get_timestamp())
+        .map(|timestamp| {
+            let (secs, nanos) = timestamp.to_unix();
+            SystemTime::UNIX_EPOCH + Duration::new(secs, nanos)
+        })
+        .ok_or(AppError::bad_request("OAuth state cookie is invalid."))?;
+
+    let expired = started_at
+        .elapsed()
+        .is_ok_and(|elapsed| elapsed > LOGIN_TIMEOUT);
+
+    if expired {
+        return Err(login_expired());
+    }
+
+    Ok(())
+}
+
+fn login_expired() -> AppError {
+    AppError::bad_request("Your login has expired. Please try logging in again.")
 }
 
 #[derive(Debug, Deserialize)]
@@ -85,6 +128,17 @@ struct DiscordUser {
     username: String,
     avatar: Option<String>,
     global_name: Option<String>,
+    #[serde(default)]
+    public_flags: i32,
+    #[serde(default)]
+    banner: Option<String>,
+    #[serde(default)]
+    avatar_decoration_data: Option<AvatarDecorationData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AvatarDecorationData {
+    asset: String,
 }
 
 impl DiscordUser {
@@ -98,37 +152,70 @@ impl DiscordUser {
 struct TokenResponse {
     access_token: String,
     refresh_token: String,
+    /// Seconds until the access token expires.
+    expires_in: u64,
+}
+
+impl TokenResponse {
+    fn new(access_token: String, refresh_token: String) -> Self {
+        Self {
+            access_token,
+            refresh_token,
+            expires_in: auth::token::EXPIRATION_TIME.as_secs(),
+        }
+    }
 }
 
+/// The callback is opened in the user's browser, so errors are shown as a page
+/// with a link to start over instead of JSON.
 async fn oauth_callback(
     Query(query): Query<CallbackQuery>,
     State(state): State<AppState>,
     cookies: CookieJar,
+) -> Response {
+    match handle_callback(query, &state, &cookies).await {
+        Ok(html) => html.into_response(),
+        // relative to /auth/callback
+        Err(err) => redirect::error_page(err, "Login failed", Some("login")),
+    }
+}
+
+async fn handle_callback(
+    query: CallbackQuery,
+    state: &AppState,
+    cookies: &CookieJar,
 ) -> AppResult<Html<String>> {
-    let oauth_state = cookies
-        .get("state")
-        .ok_or(AppError::bad_request("OAuth state cookie is missing."))?
-        .value();
+    // both cookies expire after `LOGIN_TIMEOUT`, so a missing one most likely means a stale login
+    let oauth_state = cookies.get("state").ok_or_else(login_expired)?.value();
 
     if oauth_state != query.state {
         return Err(AppError::bad_request("OAuth state parameter is invalid."));
     }
 
+    check_login_age(oauth_state)?;
+
+    let code_verifier = cookies
+        .get("code_verifier")
+        .ok_or_else(login_expired)?
+        .value();
+
     let tokens = request_token_and_create_jwt(
         DiscordTokenRequest::AuthorizationCode {
             code: &query.code,
-            redirect_uri: SERVER_REDIRECT_URI,
+            redirect_uri: &state.oauth_redirect_uri,
+            code_verifier,
         },
-        &state,
+        state,
     )
-    .await?;
+    .await;
 
-    let redirect_url = format!(
-        "gale://auth/callback?access_token={}&refresh_token={}",
-        tokens.access_token, tokens.refresh_token
-    );
+    state.metrics.record_auth(tokens.is_ok());
+    let tokens = tokens?;
 
-    Ok(RedirectBuilder::new(&redirect_url).build())
+    Ok(RedirectBuilder::new("gale://auth/callback")
+        .query("access_token", tokens.access_token)
+        .query("refresh_token", tokens.refresh_token)
+        .build())
 }
 
 #[derive(Debug, Deserialize)]
@@ -141,14 +228,42 @@ async fn grant_token(
     State(state): State<AppState>,
     Json(req): Json<GrantTokenRequest>,
 ) -> AppResult<Json<TokenResponse>> {
-    request_token_and_create_jwt(
-        DiscordTokenRequest::RefreshToken {
-            refresh_token: &req.refresh_token,
-        },
-        &state,
-    )
-    .await
-    .map(Json)
+    let tokens = refresh_tokens(&req.refresh_token, &state).await;
+
+    state.metrics.record_auth(tokens.is_ok());
+    tokens.map(Json)
+}
+
+async fn refresh_tokens(refresh_token: &str, state: &AppState) -> AppResult<TokenResponse> {
+    if let Some((user, refresh_token)) = auth::refresh::rotate(refresh_token, state).await? {
+        let access_token = auth::token::create(user.into(), state)?;
+
+        return Ok(TokenResponse::new(access_token, refresh_token));
+    }
+
+    // clients that logged in before we issued our own refresh tokens still hold discord ones
+    request_token_and_create_jwt(DiscordTokenRequest::RefreshToken { refresh_token }, state).await
+}
+
+#[derive(Debug, Serialize)]
+struct IntrospectResponse {
+    active: bool,
+    #[serde(flatten)]
+    claims: JwtClaims,
+}
+
+/// Decodes a token for debugging purposes, even if it's expired.
+async fn introspect(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> AppResult<Json<IntrospectResponse>> {
+    let token = auth::bearer_token(&headers)?;
+    let claims = auth::token::decode(token, &state)?;
+
+    Ok(Json(IntrospectResponse {
+        active: !claims.is_expired()?,
+        claims,
+    }))
 }
 
 async fn request_token_and_create_jwt(
@@ -161,12 +276,10 @@ async fn request_token_and_create_jwt(
         .context("error fetching discord auth info")?;
 
     let user = upsert_discord_user(info.user, state).await?;
-    let jwt = auth::token::create(user.into(), state)?;
+    let refresh_token = auth::refresh::create(user.id, &state.db).await?;
+    let access_token = auth::token::create(user.into(), state)?;
 
-    Ok(TokenResponse {
-        access_token: jwt,
-        refresh_token: tokens.refresh_token,
-    })
+    Ok(TokenResponse::new(access_token, refresh_token))
 }
 
 #[derive(Debug, Serialize)]
@@ -175,6 +288,8 @@ enum DiscordTokenRequest<'a> {
     AuthorizationCode {
         code: &'a str,
         redirect_uri: &'a str,
+        /// PKCE verifier matching the challenge sent in [`login`].
+        code_verifier: &'a str,
     },
     RefreshToken {
         refresh_token: &'a str,
@@ -201,7 +316,7 @@ async fn get_discord_token(
             Ok(tokens)
         }
         Err(err) if err.status() == Some(StatusCode::BAD_REQUEST) => {
-            Err(AppError::bad_request("Invalid refresh token."))
+            Err(AppError::InvalidRefreshToken)
         }
         Err(err) => Err(err.into()),
     }
@@ -222,40 +337,65 @@ async fn get_discord_auth_info(access_token: &str, state: &AppState) -> AppResul
 }
 
 async fn upsert_discord_user(user: DiscordUser, state: &AppState) -> AppResult<User> {
-    /*
-    let is_test_user = sqlx::query!(
-        "SELECT EXISTS(SELECT 1 FROM test_users WHERE discord_id = $1)",
-        user.id
-    )
-    .fetch_one(&state.db)
-    .await?
-    .exists
-    .unwrap_or(false);
-
-    if !is_test_user {
-        warn!(
-            "user {} tried to log in but wasn't whitelisted!",
-            user.display_name()
-        );
-        // TODO: nicer redirect since this is shown in browsers
-        return Err(AppError::forbidden("Profile sync is currently only available to test users. Request beta access on Discord or come back later!"));
+    if state.require_whitelist {
+        let is_test_user = sqlx::query!(
+            "SELECT EXISTS(SELECT 1 FROM test_users WHERE discord_id = $1)",
+            user.id
+        )
+        .fetch_one(&state.db)
+        .await?
+        .exists
+        .unwrap_or(false);
+
+        if !is_test_user {
+            warn!(
+                "user {} tried to log in but wasn't whitelisted!",
+                user.display_name()
+            );
+            return Err(AppError::forbidden("Profile sync is currently only available to test users. Request beta access on Discord or come back later!"));
+        }
     }
-    */
 
     let user = sqlx::query_as!(
         User,
-        "INSERT INTO users (name, display_name, discord_id, avatar)
-        VALUES ($1, $2, $3, $4)
+        "INSERT INTO users (
+            name,
+            display_name,
+            discord_id,
+            avatar,
+            public_flags,
+            banner,
+            avatar_decoration,
+            last_login_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
         ON CONFLICT(discord_id)
         DO UPDATE SET
             name = EXCLUDED.name,
             display_name = EXCLUDED.display_name,
-            avatar = EXCLUDED.avatar
-        RETURNING id, name, display_name, discord_id, avatar",
+            avatar = EXCLUDED.avatar,
+            public_flags = EXCLUDED.public_flags,
+            banner = EXCLUDED.banner,
+            avatar_decoration = EXCLUDED.avatar_decoration,
+            last_login_at = EXCLUDED.last_login_at
+        RETURNING
+            id,
+            name,
+            display_name,
+            discord_id,
+            avatar,
+            public_flags,
+            banner,
+            avatar_decoration,
+            public_id,
+            is_admin",
         user.username,
         user.display_name(),
         user.id,
         user.avatar,
+        user.public_flags,
+        user.banner,
+        user.avatar_decoration_data.as_ref().map(|data| &data.asset),
     )
     .fetch_one(&state.db)
     .await?;
@@ -0,0 +1,37 @@
+use axum::{extract::State, routing::get, Json, Router};
+use serde::Serialize;
+
+use crate::prelude::*;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(list_communities))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Community {
+    name: String,
+    profile_count: i64,
+}
+
+/// Lists the communities of public profiles, most popular first.
+/// Profiles without a community are left out.
+async fn list_communities(State(state): State<AppState>) -> AppResult<Json<Vec<Community>>> {
+    let communities = sqlx::query_as!(
+        Community,
+        r#"SELECT
+            community AS "name!",
+            COUNT(*) AS "profile_count!"
+        FROM profiles
+        WHERE
+            visibility = 'public' AND
+            deleted_at IS NULL AND
+            community IS NOT NULL
+        GROUP BY community
+        ORDER BY COUNT(*) DESC, community"#
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(communities))
+}
@@ -1,15 +1,109 @@
-use axum::{extract::Path, response::Html, routing::get, Router};
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
 
-use crate::{prelude::*, redirect::RedirectBuilder};
+use crate::{
+    prelude::*,
+    profile::ProfileId,
+    redirect::{self, RedirectBuilder},
+};
 
 pub fn routes() -> Router<AppState> {
     Router::new().route("/profile/sync/clone/{id}", get(clone_profile))
 }
 
-async fn clone_profile(Path(id): Path<String>) -> Html<String> {
-    RedirectBuilder::new(format!("gale://profile/sync/clone/{id}"))
+const APP_ICON_URL: &str =
+    "https://github.com/Kesomannen/gale/blob/master/images/icons/app-icon@0,25x.png?raw=true";
+
+/// Opened in browsers, so the id is taken as a string and invalid ones get an HTML error page.
+///
+/// Ids ending in `.json` get the link resolved as JSON instead, for clients that can't open
+/// `gale://` links.
+async fn clone_profile(Path(id): Path<String>, State(state): State<AppState>) -> Response {
+    if let Some(id) = id.strip_suffix(".json") {
+        return resolve_clone_link(id.to_owned(), &state)
+            .await
+            .into_response();
+    }
+
+    let id = match ProfileId::try_from(id) {
+        Ok(id) => id,
+        Err(err) => return redirect::error_page(err, "Invalid profile link", None),
+    };
+
+    let profile = find_profile(&id, &state).await.unwrap_or_else(|err| {
+        // the link preview isn't worth failing the redirect over
+        err.log();
+        None
+    });
+
+    let image = profile
+        .as_ref()
+        .and_then(|profile| profile.community.as_ref())
+        .and_then(|community| state.profile_config.community_images.get(community))
+        .map_or(APP_ICON_URL, String::as_str);
+
+    let description = match &profile {
+        Some(profile) => format!("{} ({id})", profile.name),
+        None => id.to_string(),
+    };
+
+    RedirectBuilder::new(deep_link(&id))
         .title("Import sync profile")
-        .description(id)
-        .image("https://github.com/Kesomannen/gale/blob/master/images/icons/app-icon@0,25x.png?raw=true")
+        .description(description)
+        .image(image)
         .build()
+        .into_response()
+}
+
+pub(crate) fn deep_link(id: &ProfileId) -> String {
+    format!("gale://profile/sync/clone/{id}")
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CloneLink {
+    id: ProfileId,
+    deep_link: String,
+    name: String,
+    community: Option<String>,
+}
+
+async fn resolve_clone_link(id: String, state: &AppState) -> AppResult<Json<CloneLink>> {
+    let id = ProfileId::try_from(id)?;
+    let profile = find_profile(&id, state).await?.ok_or(AppError::NotFound)?;
+
+    Ok(Json(CloneLink {
+        deep_link: deep_link(&id),
+        id,
+        name: profile.name,
+        community: profile.community,
+    }))
+}
+
+struct ClonedProfile {
+    name: String,
+    community: Option<String>,
+}
+
+/// Looks up a profile that may be shown to anyone with the link.
+async fn find_profile(id: &ProfileId, state: &AppState) -> AppResult<Option<ClonedProfile>> {
+    let profile = sqlx::query_as!(
+        ClonedProfile,
+        "SELECT name, community
+        FROM profiles
+        WHERE
+            short_id = $1 AND
+            deleted_at IS NULL AND
+            visibility != 'private'",
+        &*id.as_str()
+    )
+    .fetch_optional(&state.db)
+    .await?;
+
+    Ok(profile)
 }
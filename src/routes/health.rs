@@ -0,0 +1,63 @@
+use std::{future::Future, time::Duration};
+
+use axum::{extract::State, routing::get, Json, Router};
+use http::StatusCode;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::prelude::*;
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(health))
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    db: Status,
+    redis: Status,
+}
+
+async fn health(State(state): State<AppState>) -> (StatusCode, Json<HealthResponse>) {
+    let (db, redis) = tokio::join!(
+        check("database", async {
+            sqlx::query("SELECT 1").execute(&state.db).await?;
+            Ok(())
+        }),
+        check("redis", async {
+            let mut redis = state.redis.clone();
+            redis::cmd("PING").query_async::<()>(&mut redis).await?;
+            Ok(())
+        })
+    );
+
+    let status = if db == Status::Ok && redis == Status::Ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(HealthResponse { db, redis }))
+}
+
+async fn check(name: &str, future: impl Future<Output = anyhow::Result<()>>) -> Status {
+    match tokio::time::timeout(TIMEOUT, future).await {
+        Ok(Ok(())) => Status::Ok,
+        Ok(Err(err)) => {
+            warn!("{name} health check failed: {err:#}");
+            Status::Error
+        }
+        Err(_) => {
+            warn!("{name} health check timed out");
+            Status::Error
+        }
+    }
+}
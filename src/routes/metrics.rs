@@ -0,0 +1,19 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use http::header::CONTENT_TYPE;
+
+use crate::prelude::*;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(metrics))
+}
+
+async fn metrics(State(state): State<AppState>) -> AppResult<Response> {
+    let body = state.metrics.encode().map_err(anyhow::Error::from)?;
+
+    Ok(([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response())
+}
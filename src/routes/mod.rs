@@ -1,5 +1,61 @@
+use axum::extract::{rejection::PathRejection, FromRequestParts};
+use http::request::Parts;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::prelude::*;
+
+pub mod admin;
 pub mod auth;
+pub mod community;
 pub mod desktop;
+pub mod health;
+pub mod metrics;
 pub mod profile;
 pub mod socket;
 pub mod user;
+
+/// Offset pagination query parameters.
+#[derive(Debug, Deserialize)]
+pub struct Pagination {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl Pagination {
+    const DEFAULT_LIMIT: i64 = 50;
+    const MAX_LIMIT: i64 = 100;
+
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT)
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+}
+
+/// Like [`axum::extract::Path`], but rejects with our own error response.
+///
+/// This way invalid ids in the URL get the same JSON error as everything else,
+/// including the message from their `TryFrom` implementation.
+pub struct Path<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::extract::Path::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(value)) => Ok(Path(value)),
+            Err(PathRejection::FailedToDeserializePathParams(err)) => {
+                Err(AppError::bad_request(err.into_kind().to_string()))
+            }
+            Err(err) => Err(anyhow::anyhow!("failed to extract path: {err}").into()),
+        }
+    }
+}
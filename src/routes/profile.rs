@@ -1,254 +1,2064 @@
-use std::io::{Cursor, Read, Seek};
+use std::{
+    collections::HashMap,
+    io::{BufReader, Read, Seek},
+    time::Duration,
+};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use axum::{
-    body::Bytes,
-    extract::{DefaultBodyLimit, Path, State},
-    response::Redirect,
-    routing::{get, post, put},
-    Json, Router,
+    extract::{DefaultBodyLimit, Query, Request, State},
+    response::{IntoResponse, Redirect, Response},
+    routing::{delete, get, post, put},
+    Json, RequestExt, Router,
 };
+use base64::prelude::*;
 use chrono::{DateTime, Utc};
-use http::StatusCode;
-use rand::Rng;
-use serde::Serialize;
+use futures_util::StreamExt;
+use http::{
+    header::{
+        ACCEPT, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG,
+        IF_NONE_MATCH,
+    },
+    HeaderMap, HeaderName, StatusCode,
+};
+use http_body_util::LengthLimitError;
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::types::PgInterval;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use tower_http::decompression::RequestDecompressionLayer;
+use tracing::{error, warn};
 use zip::ZipArchive;
 
 use crate::{
     auth::{self, AuthUser},
     prelude::*,
-    profile::{self, ProfileId, ProfileManifest, ProfileMetadata},
+    profile::{
+        self, ConfigFiles, IdFormat, ModVersion, ProfileId, ProfileManifest, ProfileMetadata,
+        ProfileMod, ProfileSummary, TrendingProfile, TrendingWindow, Visibility,
+    },
+    rate_limit,
+    routes::{Pagination, Path},
+    thunderstore::{self, PackageInfo},
+    webhook,
 };
 
-const SIZE_LIMIT: usize = 10 * 1024 * 1024;
+const MAX_BATCH_SIZE: usize = 50;
+
+const MAX_REPORT_DETAILS_LENGTH: usize = 2000;
+const REPORT_RATE_LIMIT: u32 = 10;
+const REPORT_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// How long a generated id is reserved for the profile being created with it.
+const ID_CLAIM_TTL: Duration = Duration::from_secs(60 * 60);
+
+const MAX_MOD_COUNT: usize = 2000;
+const MAX_PROFILE_NAME_LENGTH: usize = 100;
+const MAX_MOD_NAME_LENGTH: usize = 256;
+
+pub fn routes(size_limit: usize) -> Router<AppState> {
+    // the limit is enforced while reading the decompressed body, so compressed
+    // uploads can't be used to get around it
+    let upload_layers = (
+        DefaultBodyLimit::max(size_limit),
+        RequestDecompressionLayer::new(),
+    );
 
-pub fn routes() -> Router<AppState> {
     Router::new()
-        .route(
-            "/",
-            post(create_profile).layer(DefaultBodyLimit::max(SIZE_LIMIT)),
-        )
+        .route("/", post(create_profile).layer(upload_layers.clone()))
+        .route("/{id}", put(update_profile).layer(upload_layers))
+        .route("/search", get(search_profiles))
+        .route("/recent", get(recent_profiles))
+        .route("/trending", get(trending_profiles))
+        .route("/meta/batch", post(get_profile_metadata_batch))
         .route(
             "/{id}",
-            put(update_profile).layer(DefaultBodyLimit::max(SIZE_LIMIT)),
+            get(download_profile)
+                .head(profile_exists)
+                .patch(patch_profile)
+                .delete(delete_profile),
         )
-        .route("/{id}", get(download_profile).delete(delete_profile))
         .route("/{id}/meta", get(get_profile_metadata))
+        .route("/{id}/stats", get(get_profile_stats))
+        .route("/{id}/mods", get(get_profile_mods))
+        .route("/{id}/versions", get(list_versions))
+        .route("/{id}/diff", get(diff_versions))
+        .route("/{id}/rollback/{version}", post(rollback_profile))
+        .route("/{id}/restore", post(restore_profile))
+        .route("/{id}/report", post(report_profile))
+        .route("/{id}/collaborators", post(add_collaborator))
+        .route(
+            "/{id}/collaborators/{discord_id}",
+            delete(remove_collaborator),
+        )
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadQuery {
+    /// Defaults to public for new profiles and leaves existing profiles unchanged.
+    visibility: Option<Visibility>,
 }
 
 async fn create_profile(
     AuthUser(user): AuthUser,
     State(mut state): State<AppState>,
-    body: Bytes,
+    Query(query): Query<UploadQuery>,
+    request: Request,
 ) -> AppResult<(StatusCode, Json<CreateProfileResponse>)> {
+    let idempotency_key = idempotency_key(request.headers(), &user)?;
+
+    if let Some(key) = &idempotency_key {
+        if let Some(profile) = get_idempotent_response(&state, key).await {
+            return Ok((StatusCode::CREATED, Json(profile)));
+        }
+    }
+
+    let archive = UploadedArchive::receive(request, state.profile_config.max_upload_size).await?;
     let id = generate_id(&state).await?;
 
-    let profile = upload_and_notify(id, &user, body, &mut state).await?;
+    let profile = upload_and_notify(id, &user, query.visibility, archive, true, &mut state).await?;
+
+    if let Some(key) = &idempotency_key {
+        set_idempotent_response(&state, key, &profile).await;
+    }
 
     Ok((StatusCode::CREATED, Json(profile)))
 }
 
+/// Returns the Redis key under which the response to a request with an
+/// `Idempotency-Key` header is stored. Keys are scoped to the user.
+fn idempotency_key(headers: &HeaderMap, user: &auth::User) -> AppResult<Option<String>> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+
+    let key = value
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH)
+        .ok_or_else(|| {
+            AppError::bad_request(format!(
+                "Idempotency-Key must be between 1 and {MAX_IDEMPOTENCY_KEY_LENGTH} visible ASCII characters."
+            ))
+        })?;
+
+    Ok(Some(format!("idempotency:{}:{key}", user.id)))
+}
+
+async fn get_idempotent_response(state: &AppState, key: &str) -> Option<CreateProfileResponse> {
+    let mut redis = state.redis.clone();
+
+    match redis.get::<_, Option<String>>(key).await {
+        Ok(Some(json)) => match serde_json::from_str(&json) {
+            Ok(response) => Some(response),
+            Err(err) => {
+                warn!("failed to deserialize idempotent response at {key}: {err}");
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(err) => {
+            warn!("failed to read idempotent response at {key}: {err}");
+            None
+        }
+    }
+}
+
+/// Failing to store the response only means a retry creates a duplicate, so errors are just logged.
+async fn set_idempotent_response(state: &AppState, key: &str, response: &CreateProfileResponse) {
+    let mut redis = state.redis.clone();
+
+    let json = match serde_json::to_string(response) {
+        Ok(json) => json,
+        Err(err) => {
+            warn!("failed to serialize idempotent response: {err}");
+            return;
+        }
+    };
+
+    if let Err(err) = redis
+        .set_ex::<_, _, ()>(key, json, IDEMPOTENCY_TTL.as_secs())
+        .await
+    {
+        warn!("failed to store idempotent response at {key}: {err}");
+    }
+}
+
 async fn update_profile(
     AuthUser(user): AuthUser,
     State(mut state): State<AppState>,
     Path(id): Path<ProfileId>,
-    body: Bytes,
+    Query(query): Query<UploadQuery>,
+    request: Request,
 ) -> AppResult<Json<CreateProfileResponse>> {
-    check_permission(&id, &user, &state).await?;
+    check_permission(&id, &user, Permission::Update, &state).await?;
 
-    let profile = upload_and_notify(id, &user, body, &mut state).await?;
+    let archive = UploadedArchive::receive(request, state.profile_config.max_upload_size).await?;
+    let profile =
+        upload_and_notify(id, &user, query.visibility, archive, false, &mut state).await?;
 
     Ok(Json(profile))
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PatchProfileRequest {
+    name: Option<String>,
+    community: Option<String>,
+}
+
+/// Updates a profile's name and community without touching the archive.
+async fn patch_profile(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+    Json(request): Json<PatchProfileRequest>,
+) -> AppResult<Json<ProfileMetadata>> {
+    check_permission(&id, &user, Permission::Update, &state).await?;
+
+    if let Some(name) = &request.name {
+        if name.trim().is_empty() {
+            return Err(AppError::bad_request("Profile name can't be empty."));
+        }
+
+        if name.chars().count() > MAX_PROFILE_NAME_LENGTH {
+            return Err(AppError::bad_request(format!(
+                "Profile name is too long. The maximum is {MAX_PROFILE_NAME_LENGTH} characters."
+            )));
+        }
+    }
+
+    validate_community(request.community.as_deref(), &state.profile_config)?;
+    check_profanity(
+        "Profile name",
+        request.name.as_deref(),
+        &state.profile_config,
+    )?;
+    check_profanity(
+        "Community",
+        request.community.as_deref(),
+        &state.profile_config,
+    )?;
+
+    sqlx::query!(
+        "UPDATE profiles
+        SET
+            name = COALESCE($2, name),
+            community = COALESCE($3, community),
+            updated_at = NOW(),
+            updated_by = $4
+        WHERE short_id = $1",
+        &*id.as_str(),
+        request.name,
+        request.community,
+        user.id
+    )
+    .execute(&state.db)
+    .await?;
+
+    let metadata = profile::get_uncached(&state, &id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    state.sockets.notify_profile_updated(&metadata).await;
+    webhook::notify_profile_updated(&state, &metadata);
+
+    Ok(Json(metadata))
+}
+
 async fn delete_profile(
     AuthUser(user): AuthUser,
     State(state): State<AppState>,
     Path(id): Path<ProfileId>,
 ) -> AppResult<StatusCode> {
-    check_permission(&id, &user, &state).await?;
-
-    let mut tx = state.db.begin().await?;
+    check_permission(&id, &user, Permission::Manage, &state).await?;
 
-    let _ = sqlx::query!(
-        "DELETE FROM profiles WHERE short_id = $1 RETURNING updated_at",
+    // the profile can be restored until it's purged, see `profile::purge_deleted`
+    let owner = sqlx::query!(
+        r#"UPDATE profiles p
+        SET deleted_at = NOW()
+        FROM users u
+        WHERE
+            p.short_id = $1 AND
+            p.deleted_at IS NULL AND
+            u.id = p.owner_id
+        RETURNING p.owner_id, u.discord_id"#,
         &*id.as_str()
     )
-    .fetch_optional(&mut *tx)
+    .fetch_optional(&state.db)
     .await?
     .ok_or(AppError::NotFound)?;
 
     state
         .sockets
-        .notify_profile_deleted(state.redis.clone(), &id);
+        .notify_profile_deleted(&id, &owner.discord_id)
+        .await;
+    webhook::notify_profile_deleted(&state, owner.owner_id, &id);
 
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Undoes a deletion, as long as the profile hasn't been purged yet.
+async fn restore_profile(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+) -> AppResult<Json<ProfileMetadata>> {
+    let profile = sqlx::query!(
+        "SELECT owner_id
+        FROM profiles
+        WHERE
+            short_id = $1 AND
+            deleted_at > NOW() - $2::INTERVAL",
+        &*id.as_str(),
+        PgInterval::try_from(state.profile_config.restore_window)
+            .map_err(|err| anyhow!("invalid restore window: {err}"))?
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    if profile.owner_id != user.id {
+        return Err(AppError::forbidden(
+            "User is not the owner of this profile.",
+        ));
+    }
+
+    sqlx::query!(
+        "UPDATE profiles SET deleted_at = NULL WHERE short_id = $1",
+        &*id.as_str()
+    )
+    .execute(&state.db)
+    .await?;
+
+    let metadata = profile::get_uncached(&state, &id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    state.sockets.notify_profile_updated(&metadata).await;
+    webhook::notify_profile_updated(&state, &metadata);
+
+    Ok(Json(metadata))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Permission {
+    /// Uploading new versions of the profile, allowed for the owner and collaborators.
+    Update,
+    /// Deleting the profile and managing its collaborators, only allowed for the owner.
+    Manage,
+}
+
 async fn check_permission(
     profile_id: &ProfileId,
     user: &auth::User,
+    permission: Permission,
     state: &AppState,
 ) -> Result<(), AppError> {
     let profile = sqlx::query!(
-        "SELECT owner_id FROM profiles WHERE short_id = $1",
-        &*profile_id.as_str()
+        r#"SELECT
+            p.owner_id,
+            EXISTS(
+                SELECT 1 FROM profile_collaborators c
+                WHERE c.profile_id = p.id AND c.user_id = $2
+            ) AS "is_collaborator!"
+        FROM profiles p
+        WHERE
+            p.short_id = $1 AND
+            p.deleted_at IS NULL"#,
+        &*profile_id.as_str(),
+        user.id
     )
     .fetch_optional(&state.db)
     .await?
     .ok_or(AppError::NotFound)?;
 
-    if profile.owner_id == user.id {
-        Ok(())
-    } else {
-        Err(AppError::forbidden(
+    match permission {
+        _ if profile.owner_id == user.id => Ok(()),
+        Permission::Update if profile.is_collaborator => Ok(()),
+        Permission::Update => Err(AppError::forbidden(
+            "User is not the owner or a collaborator of this profile.",
+        )),
+        Permission::Manage => Err(AppError::forbidden(
             "User is not the owner of this profile.",
-        ))
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "report_category", rename_all = "lowercase")]
+enum ReportCategory {
+    Spam,
+    Inappropriate,
+    Malicious,
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportRequest {
+    category: ReportCategory,
+    #[serde(default)]
+    details: String,
+}
+
+/// Reports a profile to the admins. Reporting the same profile again replaces the previous report.
+async fn report_profile(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+    Json(request): Json<ReportRequest>,
+) -> AppResult<StatusCode> {
+    if request.details.chars().count() > MAX_REPORT_DETAILS_LENGTH {
+        return Err(AppError::bad_request(format!(
+            "Details are too long. The maximum is {MAX_REPORT_DETAILS_LENGTH} characters."
+        )));
+    }
+
+    profile::get(&state, &id)
+        .await?
+        .filter(|metadata| metadata.is_visible_to(Some(&user)))
+        .ok_or(AppError::NotFound)?;
+
+    rate_limit::check(
+        &state,
+        &format!("report:{}", user.id),
+        REPORT_RATE_LIMIT,
+        REPORT_RATE_LIMIT_WINDOW,
+    )
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO profile_reports (profile_id, reporter_id, category, details)
+        SELECT id, $2, $3, $4
+        FROM profiles
+        WHERE short_id = $1
+        ON CONFLICT (profile_id, reporter_id)
+        DO UPDATE SET
+            category = EXCLUDED.category,
+            details = EXCLUDED.details,
+            updated_at = NOW()",
+        &*id.as_str(),
+        user.id,
+        request.category as ReportCategory,
+        request.details.trim()
+    )
+    .execute(&state.db)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AddCollaboratorRequest {
+    discord_id: String,
+}
+
+async fn add_collaborator(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+    Json(req): Json<AddCollaboratorRequest>,
+) -> AppResult<Json<Vec<auth::User>>> {
+    check_permission(&id, &user, Permission::Manage, &state).await?;
+
+    if req.discord_id == user.discord_id {
+        return Err(AppError::bad_request(
+            "The owner can't be added as a collaborator.",
+        ));
+    }
+
+    let result = sqlx::query!(
+        "INSERT INTO profile_collaborators (profile_id, user_id)
+        SELECT p.id, u.id
+        FROM profiles p, users u
+        WHERE p.short_id = $1 AND u.discord_id = $2
+        ON CONFLICT DO NOTHING",
+        &*id.as_str(),
+        req.discord_id
+    )
+    .execute(&state.db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        let user_exists = sqlx::query!(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE discord_id = $1)",
+            req.discord_id
+        )
+        .fetch_one(&state.db)
+        .await?
+        .exists
+        .unwrap_or(false);
+
+        if !user_exists {
+            return Err(AppError::bad_request(
+                "User not found. They need to log in at least once before being added.",
+            ));
+        }
+    }
+
+    let collaborators = profile::collaborators(&state, &id).await?;
+
+    state
+        .sockets
+        .notify_collaborators_changed(&id, &collaborators)
+        .await;
+
+    Ok(Json(collaborators))
+}
+
+async fn remove_collaborator(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Path((id, discord_id)): Path<(ProfileId, String)>,
+) -> AppResult<StatusCode> {
+    check_permission(&id, &user, Permission::Manage, &state).await?;
+
+    let result = sqlx::query!(
+        "DELETE FROM profile_collaborators c
+        USING profiles p, users u
+        WHERE
+            c.profile_id = p.id AND
+            c.user_id = u.id AND
+            p.short_id = $1 AND
+            u.discord_id = $2",
+        &*id.as_str(),
+        discord_id
+    )
+    .execute(&state.db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    let collaborators = profile::collaborators(&state, &id).await?;
+
+    state
+        .sockets
+        .notify_collaborators_changed(&id, &collaborators)
+        .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// A cheap way to check if a profile exists, without fetching or downloading it.
+async fn profile_exists(
+    Path(id): Path<ProfileId>,
+    State(state): State<AppState>,
+    user: Option<AuthUser>,
+) -> AppResult<StatusCode> {
+    let user_id = user.map(|AuthUser(user)| user.id);
+
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(
+            SELECT 1 FROM profiles
+            WHERE
+                short_id = $1 AND
+                deleted_at IS NULL AND
+                (visibility != 'private' OR owner_id = $2)
+        ) AS "exists!""#,
+        &*id.as_str(),
+        user_id
+    )
+    .fetch_one(&state.db)
+    .await?;
+
+    if exists {
+        Ok(StatusCode::OK)
+    } else {
+        Err(AppError::NotFound)
     }
 }
 
+#[derive(Debug, Serialize)]
+struct DownloadResponse {
+    url: String,
+}
+
+/// Redirects to the profile archive, or returns its URL as JSON if the client asks for it.
+///
+/// Profiles without a Thunderstore code are served directly from storage.
 async fn download_profile(
     Path(id): Path<ProfileId>,
     State(state): State<AppState>,
-) -> AppResult<Redirect> {
+    headers: HeaderMap,
+    user: Option<AuthUser>,
+) -> AppResult<Response> {
+    let user_id = user.map(|AuthUser(user)| user.id);
+
+    // private profiles are hidden from everyone but the owner
     let profile = sqlx::query!(
-        "UPDATE profiles
-            SET downloads = downloads + 1
-        WHERE short_id = $1
-        RETURNING 
-            updated_at,
-            code",
-        &*id.as_str()
+        r#"WITH updated AS (
+            UPDATE profiles
+                SET downloads = downloads + 1
+            WHERE
+                short_id = $1 AND
+                deleted_at IS NULL AND
+                (visibility != 'private' OR owner_id = $2)
+            RETURNING
+                id,
+                updated_at,
+                code,
+                storage_key
+        ), event AS (
+            INSERT INTO profile_downloads (profile_id)
+            SELECT id FROM updated
+        )
+        SELECT
+            updated_at AS "updated_at!",
+            code,
+            storage_key
+        FROM updated"#,
+        &*id.as_str(),
+        user_id
     )
     .fetch_optional(&state.db)
     .await?
     .ok_or(AppError::NotFound)?;
 
-    let code = profile
-        .code
-        .map(|uuid| uuid.to_string())
-        .ok_or_else(|| AppError::Other(anyhow!("profile has no thunderstore code")))?;
+    state.metrics.profile_downloads.inc();
+
+    let Some(code) = profile.code else {
+        // not mirrored to thunderstore, serve the archive from our own storage instead
+        let key = profile::current_storage_key(&id, profile.storage_key);
+        let archive = state.storage.download(key).await?;
+
+        return Ok((
+            [
+                (CONTENT_TYPE, "application/zip".to_string()),
+                (
+                    CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{id}.zip\""),
+                ),
+            ],
+            archive,
+        )
+            .into_response());
+    };
+
+    let url = thunderstore::api_url(&state, &format!("legacyprofile/get/{code}/"));
 
-    let url = format!("https://thunderstore.io/api/experimental/legacyprofile/get/{code}/");
+    let accepts_json = headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"));
 
-    Ok(Redirect::to(&url))
+    if accepts_json {
+        Ok(Json(DownloadResponse { url }).into_response())
+    } else {
+        Ok(Redirect::to(&url).into_response())
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct CreateProfileResponse {
     #[serde(rename = "id")]
     short_id: ProfileId,
+    #[serde(with = "crate::rfc3339")]
     created_at: DateTime<Utc>,
+    #[serde(with = "crate::rfc3339")]
     updated_at: DateTime<Utc>,
+    visibility: Visibility,
 }
 
-async fn upload_and_notify(
-    id: ProfileId,
-    user: &auth::User,
-    body: Bytes,
-    state: &mut AppState,
-) -> AppResult<CreateProfileResponse> {
-    let cursor = Cursor::new(body.clone());
-    // reading the zip file could be intensive
-    let manifest = tokio::task::spawn_blocking(|| read_manifest(cursor))
-        .await
-        .map_err(|err| anyhow!(err))??;
+/// An uploaded profile archive, buffered to a temporary file rather than memory.
+///
+/// The file is deleted when this is dropped.
+struct UploadedArchive {
+    file: NamedTempFile,
+    hash: Vec<u8>,
+    size: u64,
+}
 
-    let mods_json = serde_json::to_value(&manifest.mods)
-        .map_err(|err| anyhow!("failed to serialize mods: {err}"))?;
+impl UploadedArchive {
+    /// Streams the request body to disk, respecting the route's [`DefaultBodyLimit`].
+    ///
+    /// `size_limit` should be the same as the route's limit and is only used for error messages.
+    async fn receive(request: Request, size_limit: usize) -> AppResult<Self> {
+        let too_large = || {
+            AppError::payload_too_large(format!(
+                "Profile exceeds the maximum size of {}.",
+                format_size(size_limit)
+            ))
+        };
 
-    let key = profile::upload(state, body).await?;
+        // fail early instead of streaming a body that's known to be too large
+        let content_length = request
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
 
-    let profile = sqlx::query_as!(
-        CreateProfileResponse,
-        r#"INSERT INTO profiles (short_id, owner_id, name, community, mods, code)
-        VALUES ($1, $2, $3, $4, $5, $6)
-        ON CONFLICT(short_id)
-        DO UPDATE SET
-            name = EXCLUDED.name,
-            mods = EXCLUDED.mods,
-            code = EXCLUDED.code,
-            updated_at = NOW()
-        RETURNING
-            short_id AS "short_id: ProfileId", 
-            created_at,
-            updated_at"#,
-        &*id.as_str(),
-        user.id,
-        manifest.profile_name,
-        manifest.community,
-        mods_json,
-        key
-    )
-    .fetch_one(&state.db)
-    .await?;
+        if content_length.is_some_and(|length| length > size_limit) {
+            return Err(too_large());
+        }
 
-    state.sockets.notify_profile_updated(
-        state.redis.clone(),
-        &ProfileMetadata {
-            short_id: id,
-            created_at: profile.created_at,
-            updated_at: profile.updated_at,
-            owner: user.clone(),
-            manifest,
-        },
-    );
+        let temp = NamedTempFile::new().context("failed to create temporary file")?;
+        let mut file =
+            tokio::fs::File::from_std(temp.reopen().context("failed to open temporary file")?);
 
-    Ok(profile)
-}
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut stream = request.with_limited_body().into_body().into_data_stream();
 
-fn read_manifest(input: impl Read + Seek) -> AppResult<ProfileManifest> {
-    let mut input_zip = ZipArchive::new(input)
-        .map_err(|err| AppError::bad_request(format!("Invalid ZIP archive: {err}")))?;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|err| {
+                if is_length_limit_error(&err) {
+                    too_large()
+                } else {
+                    AppError::bad_request(format!("Failed to read request body: {err}"))
+                }
+            })?;
 
-    let manifest = input_zip
-        .by_name("export.r2x")
-        .map_err(|_| AppError::bad_request("Invalid ZIP archive: export.r2x file is missing"))?;
+            hasher.update(&chunk);
+            size += chunk.len() as u64;
+            file.write_all(&chunk)
+                .await
+                .context("failed to write to temporary file")?;
+        }
+
+        file.flush()
+            .await
+            .context("failed to write to temporary file")?;
 
-    let manifest: ProfileManifest = serde_yml::from_reader(manifest)
-        .map_err(|err| AppError::bad_request(format!("Error parsing export.r2x: {err}")))?;
+        Ok(Self {
+            file: temp,
+            hash: hasher.finalize().to_vec(),
+            size,
+        })
+    }
 
-    Ok(manifest)
+    fn path(&self) -> &std::path::Path {
+        self.file.path()
+    }
 }
 
-async fn get_profile_metadata(
-    State(state): State<AppState>,
-    Path(id): Path<ProfileId>,
-) -> AppResult<Json<ProfileMetadata>> {
-    let profile = crate::profile::get(&state, &id)
-        .await?
-        .ok_or(AppError::NotFound)?;
+/// Formats a byte count like `2 MiB` or `1.5 MiB`.
+fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["bytes", "KiB", "MiB", "GiB"];
 
-    Ok(Json(profile))
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if size.fract() == 0.0 {
+        format!("{size} {}", UNITS[unit])
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
 }
 
-async fn generate_id(state: &AppState) -> AppResult<ProfileId> {
-    loop {
-        let id: String = rand::rng()
-            .sample_iter(rand::distr::Alphanumeric)
-            .take(6)
-            .map(char::from)
-            .map(|c| c.to_ascii_uppercase())
-            .collect();
+fn is_length_limit_error(err: &axum::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
 
-        if rustrict::CensorStr::is_inappropriate(&*id) {
-            continue;
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return true;
         }
 
-        let exists = sqlx::query!(
-            "SELECT EXISTS(SELECT 1 FROM profiles WHERE short_id = $1)",
-            &id
+        source = err.source();
+    }
+
+    false
+}
+
+/// Uploads a new archive for the profile.
+///
+/// If `create` is set the profile is inserted and never overwrites an existing one.
+/// If `id` turns out to be taken, another one is generated. Otherwise the profile
+/// must already exist.
+async fn upload_and_notify(
+    mut id: ProfileId,
+    user: &auth::User,
+    visibility: Option<Visibility>,
+    archive: UploadedArchive,
+    create: bool,
+    state: &mut AppState,
+) -> AppResult<CreateProfileResponse> {
+    let content_hash = archive.hash.clone();
+
+    let existing = if create {
+        None
+    } else {
+        sqlx::query_as!(
+            ExistingProfile,
+            r#"SELECT
+            short_id AS "short_id: ProfileId",
+            created_at,
+            updated_at,
+            visibility AS "visibility: Visibility",
+            content_hash,
+            deleted_at IS NOT NULL AS "deleted!"
+        FROM profiles
+        WHERE short_id = $1"#,
+            &*id.as_str()
         )
-        .fetch_one(&state.db)
+        .fetch_optional(&state.db)
         .await?
-        .exists
-        .unwrap_or(true);
+    };
 
-        if exists {
+    // bail before uploading anything if there's nothing to do
+    let insert = match upload_action(create, existing, &content_hash, visibility)? {
+        UploadAction::Insert => true,
+        UploadAction::Update => false,
+        UploadAction::Unchanged(profile) => return Ok(profile),
+    };
+
+    check_quota(&id, user, archive.size, state).await?;
+
+    let file = archive
+        .file
+        .reopen()
+        .context("failed to open temporary file")?;
+    let max_unpacked_size = state.profile_config.max_unpacked_size;
+    // reading the zip file could be intensive
+    let (manifest, config_files) =
+        tokio::task::spawn_blocking(move || read_manifest(BufReader::new(file), max_unpacked_size))
+            .await
+            .map_err(|err| anyhow!(err))??;
+
+    validate_community(manifest.community.as_deref(), &state.profile_config)?;
+    check_profanity(
+        "Profile name",
+        Some(&manifest.profile_name),
+        &state.profile_config,
+    )?;
+    check_profanity(
+        "Community",
+        manifest.community.as_deref(),
+        &state.profile_config,
+    )?;
+
+    let mods_json = serde_json::to_value(&manifest.mods)
+        .map_err(|err| anyhow!("failed to serialize mods: {err}"))?;
+
+    let storage_key = profile::new_storage_key();
+
+    let (code, stored) = tokio::join!(
+        profile::upload_file(state, archive.path()),
+        state
+            .storage
+            .upload_file(&storage_key, archive.path(), "application/zip")
+    );
+
+    stored?;
+
+    // from here on the archive is deleted again if anything fails, see `discard_upload`
+    let result = async {
+        let code = code?;
+        let mut tx = state.db.begin().await?;
+
+        let profile = if insert {
+            loop {
+                let inserted = sqlx::query_as!(
+                    CreateProfileResponse,
+                    r#"INSERT INTO profiles (
+                        short_id,
+                        owner_id,
+                        name,
+                        community,
+                        mods,
+                        code,
+                        visibility,
+                        content_hash,
+                        updated_by,
+                        size_bytes,
+                        config_file_count,
+                        config_size_bytes,
+                        storage_key
+                    )
+                    VALUES (
+                        $1, $2, $3, $4, $5, $6, COALESCE($7, 'public'::profile_visibility), $8, $2, $9, $10, $11, $12
+                    )
+                    ON CONFLICT(short_id) DO NOTHING
+                    RETURNING
+                        short_id AS "short_id: ProfileId",
+                        created_at,
+                        updated_at,
+                        visibility AS "visibility: Visibility""#,
+                    &*id.as_str(),
+                    user.id,
+                    manifest.profile_name,
+                    manifest.community,
+                    mods_json,
+                    code,
+                    visibility as Option<Visibility>,
+                    content_hash,
+                    archive.size as i64,
+                    config_files.count,
+                    config_files.size_bytes,
+                    storage_key
+                )
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                match inserted {
+                    Some(profile) => break profile,
+                    // ids are claimed in `generate_id`, but the claim is skipped if Redis is down
+                    None => {
+                        warn!("generated profile id {id} was already taken, picking another");
+                        id = generate_id(state).await?;
+                    }
+                }
+            }
+        } else {
+            sqlx::query_as!(
+                CreateProfileResponse,
+                r#"UPDATE profiles
+                SET
+                    name = $3,
+                    mods = $4,
+                    code = $5,
+                    visibility = COALESCE($6, visibility),
+                    content_hash = $7,
+                    updated_at = NOW(),
+                    updated_by = $2,
+                    size_bytes = $8,
+                    config_file_count = $9,
+                    config_size_bytes = $10,
+                    storage_key = $11
+                WHERE
+                    short_id = $1 AND
+                    deleted_at IS NULL
+                RETURNING
+                    short_id AS "short_id: ProfileId",
+                    created_at,
+                    updated_at,
+                    visibility AS "visibility: Visibility""#,
+                &*id.as_str(),
+                user.id,
+                manifest.profile_name,
+                mods_json,
+                code,
+                visibility as Option<Visibility>,
+                content_hash,
+                archive.size as i64,
+                config_files.count,
+                config_files.size_bytes,
+                storage_key
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(AppError::NotFound)?
+        };
+
+        let pruned = record_version(&mut tx, &id, state.profile_config.max_versions).await?;
+
+        tx.commit().await?;
+
+        AppResult::Ok((profile, pruned))
+    }
+    .await;
+
+    let (profile, pruned) = match result {
+        Ok(saved) => saved,
+        Err(err) => {
+            discard_upload(&storage_key, state).await;
+            return Err(err);
+        }
+    };
+
+    state.metrics.profile_uploads.inc();
+
+    for key in pruned {
+        if let Err(err) = state.storage.delete(&key).await {
+            error!("failed to delete old version of profile {id} at {key}: {err:#}");
+        }
+    }
+
+    // the uploader might be a collaborator rather than the owner, so fetch the full metadata
+    if let Some(metadata) = profile::get_uncached(state, &id).await? {
+        state.sockets.notify_profile_updated(&metadata).await;
+        webhook::notify_profile_updated(state, &metadata);
+
+        if metadata.visibility == Visibility::Public {
+            webhook::announce_profile(state, &metadata, insert);
+        }
+    }
+
+    Ok(profile)
+}
+
+/// Saves the profile's current state as a new version and deletes the versions
+/// that no longer fit in `max_versions`.
+///
+/// The profile's row must already be locked by an update in `tx`, so that concurrent
+/// changes can't pick the same version. Returns the storage keys of pruned archives
+/// that nothing points to anymore.
+async fn record_version(
+    tx: &mut sqlx::PgConnection,
+    id: &ProfileId,
+    max_versions: i32,
+) -> AppResult<Vec<String>> {
+    let version = sqlx::query_scalar!(
+        r#"SELECT COALESCE(MAX(v.version), 0) + 1 AS "version!"
+        FROM profile_versions v
+        JOIN profiles p ON p.id = v.profile_id
+        WHERE p.short_id = $1"#,
+        &*id.as_str()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO profile_versions (
+            profile_id,
+            version,
+            storage_key,
+            name,
+            community,
+            mods,
+            code,
+            config_file_count,
+            config_size_bytes,
+            content_hash,
+            size_bytes
+        )
+        SELECT
+            id,
+            $2,
+            storage_key,
+            name,
+            community,
+            mods,
+            code,
+            config_file_count,
+            config_size_bytes,
+            content_hash,
+            size_bytes
+        FROM profiles
+        WHERE short_id = $1",
+        &*id.as_str(),
+        version
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let pruned = sqlx::query_scalar!(
+        "DELETE FROM profile_versions v
+        USING profiles p
+        WHERE
+            p.id = v.profile_id AND
+            p.short_id = $1 AND
+            v.version <= $2
+        RETURNING v.storage_key",
+        &*id.as_str(),
+        version - max_versions
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    // rollbacks make several versions share an archive
+    let unused = sqlx::query_scalar!(
+        r#"SELECT DISTINCT key AS "key!"
+        FROM UNNEST($1::TEXT[]) AS key
+        WHERE
+            NOT EXISTS (SELECT 1 FROM profile_versions WHERE storage_key = key) AND
+            NOT EXISTS (SELECT 1 FROM profiles WHERE storage_key = key)"#,
+        &pruned
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    Ok(unused)
+}
+
+/// The current state of a profile that's being updated.
+struct ExistingProfile {
+    short_id: ProfileId,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    visibility: Visibility,
+    content_hash: Option<Vec<u8>>,
+    deleted: bool,
+}
+
+/// What [`upload_and_notify`] does with an uploaded archive.
+#[derive(Debug)]
+enum UploadAction {
+    /// Insert a new profile, picking another id if it's taken.
+    Insert,
+    /// Replace the archive of an existing profile.
+    Update,
+    /// The archive and visibility are the same as before, so the current metadata is returned as-is.
+    Unchanged(CreateProfileResponse),
+}
+
+/// Creates never look at existing profiles, so they can't overwrite one.
+/// Updates never create a profile, so they fail if it's missing or deleted.
+fn upload_action(
+    create: bool,
+    existing: Option<ExistingProfile>,
+    content_hash: &[u8],
+    visibility: Option<Visibility>,
+) -> AppResult<UploadAction> {
+    if create {
+        return Ok(UploadAction::Insert);
+    }
+
+    let existing = existing
+        .filter(|existing| !existing.deleted)
+        .ok_or(AppError::NotFound)?;
+
+    // clients tend to re-upload unchanged profiles
+    let unchanged = existing.content_hash.as_deref() == Some(content_hash)
+        && visibility.is_none_or(|visibility| visibility == existing.visibility);
+
+    if !unchanged {
+        return Ok(UploadAction::Update);
+    }
+
+    Ok(UploadAction::Unchanged(CreateProfileResponse {
+        short_id: existing.short_id,
+        created_at: existing.created_at,
+        updated_at: existing.updated_at,
+        visibility: existing.visibility,
+    }))
+}
+
+/// Deletes the archive of an upload that failed after it was stored.
+///
+/// The archive has a key of its own, so nothing else can point to it.
+async fn discard_upload(key: &str, state: &AppState) {
+    if let Err(err) = state.storage.delete(key).await {
+        error!("failed to delete orphaned archive at {key}: {err:#}");
+    }
+}
+
+/// r2modman's YAML export manifest, which is preferred if both are present.
+const R2X_MANIFEST: &str = "export.r2x";
+/// The same schema as JSON, used by some other mod managers.
+const JSON_MANIFEST: &str = "manifest.json";
+
+fn read_manifest(
+    input: impl Read + Seek,
+    max_unpacked_size: u64,
+) -> AppResult<(ProfileManifest, ConfigFiles)> {
+    let mut input_zip = ZipArchive::new(input)
+        .map_err(|err| AppError::bad_request(format!("Invalid ZIP archive: {err}")))?;
+
+    let config_files = validate_entries(&mut input_zip, max_unpacked_size)?;
+
+    let manifest: ProfileManifest = if input_zip.index_for_name(R2X_MANIFEST).is_some() {
+        let file = open_manifest(&mut input_zip, R2X_MANIFEST)?;
+
+        serde_yml::from_reader(file)
+            .map_err(|err| AppError::bad_request(format!("Error parsing {R2X_MANIFEST}: {err}")))?
+    } else if input_zip.index_for_name(JSON_MANIFEST).is_some() {
+        let file = open_manifest(&mut input_zip, JSON_MANIFEST)?;
+
+        serde_json::from_reader(file)
+            .map_err(|err| AppError::bad_request(format!("Error parsing {JSON_MANIFEST}: {err}")))?
+    } else {
+        return Err(AppError::bad_request(format!(
+            "Invalid ZIP archive: no manifest found, expected {R2X_MANIFEST} or {JSON_MANIFEST}"
+        )));
+    };
+
+    validate_manifest(&manifest)?;
+
+    Ok((manifest, config_files))
+}
+
+/// Reads at most the entry's declared size, which is what [`validate_entries`] checked.
+fn open_manifest<'a>(
+    zip: &'a mut ZipArchive<impl Read + Seek>,
+    name: &str,
+) -> AppResult<impl Read + 'a> {
+    let file = zip.by_name(name).context("failed to read manifest")?;
+    let size = file.size();
+
+    Ok(file.take(size))
+}
+
+/// Checks that replacing the profile's archive with one of `size` bytes keeps
+/// its owner within [`profile::Config::storage_quota`].
+async fn check_quota(
+    id: &ProfileId,
+    user: &auth::User,
+    size: u64,
+    state: &AppState,
+) -> AppResult<()> {
+    let Some(quota) = state.profile_config.storage_quota else {
+        return Ok(());
+    };
+
+    // collaborators' uploads count towards the owner's quota
+    let used = profile::storage_used(state, id, user.id).await?;
+
+    if used + size > quota {
+        return Err(AppError::forbidden(format!(
+            "Storage quota exceeded. You are using {used} of {quota} bytes and this profile is {size} bytes."
+        )));
+    }
+
+    Ok(())
+}
+
+fn validate_community(community: Option<&str>, config: &profile::Config) -> AppResult<()> {
+    match community {
+        Some(community) if !config.is_community_allowed(community) => Err(AppError::bad_request(
+            format!("Unknown community: {community}."),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Names are shown in discovery, so deployments can opt into keeping them clean.
+fn check_profanity(field: &str, value: Option<&str>, config: &profile::Config) -> AppResult<()> {
+    match value {
+        Some(value) if config.reject_profanity && rustrict::CensorStr::is_inappropriate(value) => {
+            Err(AppError::bad_request(format!(
+                "{field} contains inappropriate language."
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_manifest(manifest: &ProfileManifest) -> AppResult<()> {
+    if manifest.mods.len() > MAX_MOD_COUNT {
+        return Err(AppError::bad_request(format!(
+            "Profile has too many mods ({}). The maximum is {MAX_MOD_COUNT}.",
+            manifest.mods.len()
+        )));
+    }
+
+    if manifest.profile_name.chars().count() > MAX_PROFILE_NAME_LENGTH {
+        return Err(AppError::bad_request(format!(
+            "Profile name is too long. The maximum is {MAX_PROFILE_NAME_LENGTH} characters."
+        )));
+    }
+
+    if let Some(profile_mod) = manifest
+        .mods
+        .iter()
+        .find(|profile_mod| profile_mod.name.chars().count() > MAX_MOD_NAME_LENGTH)
+    {
+        return Err(AppError::bad_request(format!(
+            "Mod name {}... is too long. The maximum is {MAX_MOD_NAME_LENGTH} characters.",
+            profile_mod.name.chars().take(32).collect::<String>()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Rejects zip bombs and entries that would escape the directory they're extracted to.
+/// Checks the paths and total size of the archive's entries, and counts the files
+/// besides the manifest.
+fn validate_entries(
+    zip: &mut ZipArchive<impl Read + Seek>,
+    max_unpacked_size: u64,
+) -> AppResult<ConfigFiles> {
+    let mut total_size: u64 = 0;
+    let mut config_files = ConfigFiles::default();
+
+    for i in 0..zip.len() {
+        // raw access doesn't decompress the entry
+        let entry = zip
+            .by_index_raw(i)
+            .map_err(|err| AppError::bad_request(format!("Invalid ZIP archive: {err}")))?;
+
+        if !is_safe_path(entry.name()) {
+            return Err(AppError::bad_request(format!(
+                "Invalid ZIP archive: entry {} has an absolute or parent-relative path",
+                entry.name()
+            )));
+        }
+
+        total_size = total_size.saturating_add(entry.size());
+
+        if total_size > max_unpacked_size {
+            return Err(AppError::bad_request(format!(
+                "Invalid ZIP archive: uncompressed size exceeds the maximum of {}",
+                format_size(max_unpacked_size as usize)
+            )));
+        }
+
+        if !entry.is_dir() && ![R2X_MANIFEST, JSON_MANIFEST].contains(&entry.name()) {
+            config_files.count += 1;
+            config_files.size_bytes += entry.size() as i64;
+        }
+    }
+
+    Ok(config_files)
+}
+
+fn is_safe_path(name: &str) -> bool {
+    !name.starts_with(['/', '\\'])
+        // windows drive letters
+        && !name.contains(':')
+        && name.split(['/', '\\']).all(|part| part != "..")
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataQuery {
+    /// Look up each mod on Thunderstore.
+    #[serde(default)]
+    resolve: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MetadataResponse {
+    #[serde(flatten)]
+    metadata: ProfileMetadata,
+    /// Keyed by mod name. Only present if requested, and missing any mods that couldn't be resolved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packages: Option<HashMap<String, PackageInfo>>,
+}
+
+async fn get_profile_metadata(
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+    Query(query): Query<MetadataQuery>,
+    headers: HeaderMap,
+    user: Option<AuthUser>,
+) -> AppResult<Response> {
+    // lets us check the database directly when debugging stale data
+    let bypass_cache = headers
+        .get(CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("no-cache"));
+
+    let profile = if bypass_cache {
+        profile::get_uncached(&state, &id).await?
+    } else {
+        profile::get(&state, &id).await?
+    };
+
+    let profile = profile
+        .filter(|profile| profile.is_visible_to(user.as_ref().map(|AuthUser(user)| user)))
+        .ok_or(AppError::NotFound)?;
+
+    let packages = if query.resolve {
+        let names = profile
+            .manifest
+            .mods
+            .iter()
+            .map(|profile_mod| profile_mod.name.clone())
+            .collect();
+
+        Some(thunderstore::resolve_packages(&state, names).await)
+    } else {
+        None
+    };
+
+    let body = serde_json::to_vec(&MetadataResponse {
+        metadata: profile,
+        packages,
+    })
+    .context("failed to serialize metadata")?;
+
+    // hashing the body catches every change, including to collaborators and download counts
+    let etag = format!(
+        "\"{}\"",
+        BASE64_URL_SAFE_NO_PAD.encode(&Sha256::digest(&body)[..16])
+    );
+
+    if matches_etag(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+
+    Ok((
+        [(CONTENT_TYPE, "application/json".to_string()), (ETAG, etag)],
+        body,
+    )
+        .into_response())
+}
+
+fn matches_etag(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchMetadataRequest {
+    ids: Vec<ProfileId>,
+}
+
+/// Fetches the metadata of several profiles in one go. Missing and hidden profiles are left out.
+async fn get_profile_metadata_batch(
+    State(state): State<AppState>,
+    user: Option<AuthUser>,
+    Json(request): Json<BatchMetadataRequest>,
+) -> AppResult<Json<HashMap<String, ProfileMetadata>>> {
+    if request.ids.len() > MAX_BATCH_SIZE {
+        return Err(AppError::bad_request(format!(
+            "Too many ids. The maximum is {MAX_BATCH_SIZE}."
+        )));
+    }
+
+    let user = user.as_ref().map(|AuthUser(user)| user);
+
+    let profiles = profile::get_many(&state, &request.ids)
+        .await?
+        .into_iter()
+        .filter(|profile| profile.is_visible_to(user))
+        .map(|profile| (profile.short_id.to_string(), profile))
+        .collect();
+
+    Ok(Json(profiles))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileStats {
+    downloads: i32,
+    #[serde(with = "crate::rfc3339")]
+    created_at: DateTime<Utc>,
+    #[serde(with = "crate::rfc3339")]
+    updated_at: DateTime<Utc>,
+}
+
+async fn get_profile_stats(
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+    user: Option<AuthUser>,
+) -> AppResult<Json<ProfileStats>> {
+    let profile = crate::profile::get(&state, &id)
+        .await?
+        .filter(|profile| profile.is_visible_to(user.as_ref().map(|AuthUser(user)| user)))
+        .ok_or(AppError::NotFound)?;
+
+    Ok(Json(ProfileStats {
+        downloads: profile.downloads,
+        created_at: profile.created_at,
+        updated_at: profile.updated_at,
+    }))
+}
+
+/// Only the mod list, without the owner or other metadata.
+async fn get_profile_mods(
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+    user: Option<AuthUser>,
+) -> AppResult<Json<Vec<ProfileMod>>> {
+    let user_id = user.map(|AuthUser(user)| user.id);
+
+    let mods = sqlx::query_scalar!(
+        r#"SELECT mods AS "mods: sqlx::types::Json<Vec<ProfileMod>>"
+        FROM profiles
+        WHERE
+            short_id = $1 AND
+            deleted_at IS NULL AND
+            (visibility != 'private' OR owner_id = $2)"#,
+        &*id.as_str(),
+        user_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    Ok(Json(mods.0))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileVersion {
+    version: i32,
+    name: String,
+    community: Option<String>,
+    #[serde(with = "crate::rfc3339")]
+    created_at: DateTime<Utc>,
+}
+
+async fn list_versions(
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+    user: Option<AuthUser>,
+) -> AppResult<Json<Vec<ProfileVersion>>> {
+    crate::profile::get(&state, &id)
+        .await?
+        .filter(|profile| profile.is_visible_to(user.as_ref().map(|AuthUser(user)| user)))
+        .ok_or(AppError::NotFound)?;
+
+    let versions = sqlx::query_as!(
+        ProfileVersion,
+        "SELECT v.version, v.name, v.community, v.created_at
+        FROM profile_versions v
+        JOIN profiles p ON p.id = v.profile_id
+        WHERE p.short_id = $1
+        ORDER BY v.version DESC",
+        &*id.as_str()
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(versions))
+}
+
+#[derive(Debug, Deserialize)]
+struct DiffQuery {
+    from: i32,
+    to: i32,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionDiff {
+    added: Vec<ProfileMod>,
+    removed: Vec<ProfileMod>,
+    updated: Vec<UpdatedMod>,
+}
+
+/// A mod that's in both versions, but with a different version or enabled state.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdatedMod {
+    name: String,
+    from: ModVersion,
+    to: ModVersion,
+    enabled: bool,
+}
+
+/// Compares the mods of two versions of a profile, matching them by name.
+async fn diff_versions(
+    State(state): State<AppState>,
+    Path(id): Path<ProfileId>,
+    Query(query): Query<DiffQuery>,
+    user: Option<AuthUser>,
+) -> AppResult<Json<VersionDiff>> {
+    crate::profile::get(&state, &id)
+        .await?
+        .filter(|profile| profile.is_visible_to(user.as_ref().map(|AuthUser(user)| user)))
+        .ok_or(AppError::NotFound)?;
+
+    let versions = sqlx::query!(
+        "SELECT v.version, v.mods
+        FROM profile_versions v
+        JOIN profiles p ON p.id = v.profile_id
+        WHERE p.short_id = $1 AND v.version IN ($2, $3)",
+        &*id.as_str(),
+        query.from,
+        query.to
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mods = |version: i32| -> AppResult<Vec<ProfileMod>> {
+        let record = versions
+            .iter()
+            .find(|record| record.version == version)
+            .ok_or_else(|| AppError::bad_request(format!("Version {version} doesn't exist.")))?;
+
+        serde_json::from_value(record.mods.clone())
+            .map_err(|err| anyhow!("failed to parse mods of version {version}: {err}").into())
+    };
+
+    Ok(Json(diff_mods(mods(query.from)?, mods(query.to)?)))
+}
+
+fn diff_mods(from: Vec<ProfileMod>, to: Vec<ProfileMod>) -> VersionDiff {
+    let mut from: HashMap<String, ProfileMod> = from
+        .into_iter()
+        .map(|profile_mod| (profile_mod.name.clone(), profile_mod))
+        .collect();
+
+    let mut diff = VersionDiff::default();
+
+    for new in to {
+        match from.remove(&new.name) {
+            Some(old) if old.version != new.version || old.enabled != new.enabled => {
+                diff.updated.push(UpdatedMod {
+                    name: new.name,
+                    from: old.version,
+                    to: new.version,
+                    enabled: new.enabled,
+                });
+            }
+            Some(_) => (),
+            None => diff.added.push(new),
+        }
+    }
+
+    diff.removed.extend(from.into_values());
+
+    diff.added.sort_by(|a, b| a.name.cmp(&b.name));
+    diff.removed.sort_by(|a, b| a.name.cmp(&b.name));
+    diff.updated.sort_by(|a, b| a.name.cmp(&b.name));
+
+    diff
+}
+
+/// Restores a previous version as the current one.
+async fn rollback_profile(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Path((id, version)): Path<(ProfileId, i32)>,
+) -> AppResult<Json<ProfileMetadata>> {
+    check_permission(&id, &user, Permission::Manage, &state).await?;
+
+    let mut tx = state.db.begin().await?;
+
+    // versions from before sizes were recorded keep the current size
+    let result = sqlx::query!(
+        "UPDATE profiles p
+        SET
+            name = v.name,
+            community = v.community,
+            mods = v.mods,
+            code = v.code,
+            content_hash = v.content_hash,
+            updated_at = NOW(),
+            updated_by = $3,
+            size_bytes = COALESCE(v.size_bytes, p.size_bytes),
+            config_file_count = v.config_file_count,
+            config_size_bytes = v.config_size_bytes,
+            storage_key = v.storage_key
+        FROM profile_versions v
+        WHERE
+            v.profile_id = p.id AND
+            p.short_id = $1 AND
+            v.version = $2",
+        &*id.as_str(),
+        version,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    // the rollback becomes the latest version, so it can be undone like any other change
+    let pruned = record_version(&mut tx, &id, state.profile_config.max_versions).await?;
+
+    tx.commit().await?;
+
+    for key in pruned {
+        if let Err(err) = state.storage.delete(&key).await {
+            error!("failed to delete old version of profile {id} at {key}: {err:#}");
+        }
+    }
+
+    let metadata = profile::get_uncached(&state, &id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    state.sockets.notify_profile_updated(&metadata).await;
+    webhook::notify_profile_updated(&state, &metadata);
+
+    Ok(Json(metadata))
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    q: String,
+}
+
+async fn search_profiles(
+    State(state): State<AppState>,
+    Query(search): Query<SearchQuery>,
+    Query(pagination): Query<Pagination>,
+) -> AppResult<Json<Vec<ProfileSummary>>> {
+    let query = search.q.trim();
+
+    if query.is_empty() {
+        return Err(AppError::bad_request("Search query can't be empty."));
+    }
+
+    let profiles = profile::search(&state, query, pagination.limit(), pagination.offset()).await?;
+
+    Ok(Json(profiles))
+}
+
+#[derive(Debug, Deserialize)]
+struct CursorQuery {
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecentProfilesResponse {
+    profiles: Vec<ProfileSummary>,
+    /// Pass as `cursor` to get the next page. `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+async fn recent_profiles(
+    State(state): State<AppState>,
+    Query(query): Query<CursorQuery>,
+    Query(pagination): Query<Pagination>,
+) -> AppResult<Json<RecentProfilesResponse>> {
+    let cursor = query.cursor.as_deref().map(str::parse).transpose()?;
+
+    let (profiles, next) = profile::recent(&state, pagination.limit(), cursor).await?;
+
+    Ok(Json(RecentProfilesResponse {
+        profiles,
+        next_cursor: next.map(|cursor| cursor.to_string()),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct TrendingQuery {
+    #[serde(default)]
+    window: TrendingWindow,
+}
+
+async fn trending_profiles(
+    State(state): State<AppState>,
+    Query(query): Query<TrendingQuery>,
+    Query(pagination): Query<Pagination>,
+) -> AppResult<Json<Vec<TrendingProfile>>> {
+    let profiles = profile::trending(
+        &state,
+        query.window,
+        pagination.limit(),
+        pagination.offset(),
+    )
+    .await?;
+
+    Ok(Json(profiles))
+}
+
+/// Picks an unused id for a new profile.
+///
+/// The id is claimed in Redis so that concurrent creates can't pick the same one
+/// between this check and the insert. Taken or claimed ids are skipped.
+async fn generate_id(state: &AppState) -> AppResult<ProfileId> {
+    loop {
+        let id = IdFormat::current().generate();
+
+        if rustrict::CensorStr::is_inappropriate(&*id) {
+            continue;
+        }
+
+        let exists = sqlx::query!(
+            "SELECT EXISTS(SELECT 1 FROM profiles WHERE short_id = $1)",
+            &id
+        )
+        .fetch_one(&state.db)
+        .await?
+        .exists
+        .unwrap_or(true);
+
+        if exists || !claim_id(&id, state).await {
             continue;
         }
 
         return Ok(ProfileId::Short(id));
     }
 }
+
+/// Returns `false` if the id was already claimed by another request.
+async fn claim_id(id: &str, state: &AppState) -> bool {
+    let mut redis = state.redis.clone();
+
+    let options = SetOptions::default()
+        .conditional_set(ExistenceCheck::NX)
+        .with_expiration(SetExpiry::EX(ID_CLAIM_TTL.as_secs()));
+
+    match redis
+        .set_options::<_, _, Option<String>>(format!("profile_id_claim:{id}"), 1, options)
+        .await
+    {
+        Ok(reply) => reply.is_some(),
+        Err(err) => {
+            // the insert still refuses to overwrite an existing profile
+            warn!("failed to claim profile id {id}: {err}");
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use axum::body::Body;
+    use http::header::AUTHORIZATION;
+    use http_body_util::BodyExt;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+    use crate::testing::{self, TestApp};
+
+    const HASH: &[u8] = &[1, 2, 3];
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (name, contents) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    async fn archive(name: &str) -> UploadedArchive {
+        let manifest = format!(r#"{{ "profileName": "{name}", "mods": [] }}"#);
+        let bytes = zip(&[(JSON_MANIFEST, manifest.as_bytes())]);
+
+        UploadedArchive::receive(Request::new(bytes.into()), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    /// Storage requests for archives, formatted like `POST <key>`.
+    fn archive_requests(app: &TestApp) -> Vec<String> {
+        app.requests()
+            .into_iter()
+            .filter_map(|request| {
+                let (method, path) = request.split_once(' ')?;
+                let key = path.strip_prefix(&testing::object_path(""))?;
+                Some(format!("{method} {key}"))
+            })
+            .collect()
+    }
+
+    async fn assert_upload_discarded(app: &TestApp) {
+        let mut state = app.state.clone();
+        let id = ProfileId::Short("ABC123".to_owned());
+
+        let result = upload_and_notify(
+            id,
+            &testing::user(),
+            None,
+            archive("Test").await,
+            true,
+            &mut state,
+        )
+        .await;
+        assert!(result.is_err());
+
+        let requests = archive_requests(app);
+        let [upload, delete] = requests.as_slice() else {
+            panic!("expected an upload and a delete, got {requests:?}");
+        };
+        assert!(upload.starts_with("POST archives/"));
+        assert_eq!(delete.replacen("DELETE", "POST", 1), *upload);
+    }
+
+    #[tokio::test]
+    async fn failed_database_update_discards_archive() {
+        let app = TestApp::without_db().await;
+        assert_upload_discarded(&app).await;
+    }
+
+    #[tokio::test]
+    async fn failed_thunderstore_upload_discards_archive() {
+        let app = TestApp::without_db().await;
+        app.fail_requests("/api/experimental/legacyprofile/create/");
+        assert_upload_discarded(&app).await;
+    }
+
+    fn existing(deleted: bool) -> ExistingProfile {
+        ExistingProfile {
+            short_id: ProfileId::Short("ABC123".to_owned()),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            visibility: Visibility::Public,
+            content_hash: Some(HASH.to_vec()),
+            deleted,
+        }
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, see docs/hosting.md"]
+    async fn uploads_notify_subscribers(db: PgPool) {
+        let app = TestApp::new(db).await;
+        let user = testing::insert_user(&app.state.db).await;
+        let mut state = app.state.clone();
+
+        let profile = upload_and_notify(
+            ProfileId::Short("ABC123".to_owned()),
+            &user,
+            None,
+            archive("Test").await,
+            true,
+            &mut state,
+        )
+        .await
+        .unwrap();
+
+        let published = app.published();
+        let channels: Vec<_> = published.iter().map(|(channel, _)| channel).collect();
+        assert_eq!(
+            channels,
+            [
+                &format!("profile-update:{}", profile.short_id),
+                &format!("user-profile-update:{}", user.discord_id),
+            ]
+        );
+
+        for (_, payload) in &published {
+            let metadata: ProfileMetadata = serde_json::from_str(payload).unwrap();
+            assert_eq!(metadata.short_id, profile.short_id);
+            assert_eq!(metadata.manifest.profile_name, "Test");
+            assert_eq!(metadata.owner.public_id, user.public_id);
+        }
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, see docs/hosting.md"]
+    async fn rollback_restores_recorded_version(db: PgPool) {
+        let app = TestApp::new(db).await;
+        let user = testing::insert_user(&app.state.db).await;
+        let id = ProfileId::Short("ABC123".to_owned());
+        let mut state = app.state.clone();
+
+        for (name, create) in [("First", true), ("Second", false)] {
+            upload_and_notify(
+                id.clone(),
+                &user,
+                None,
+                archive(name).await,
+                create,
+                &mut state,
+            )
+            .await
+            .unwrap();
+        }
+
+        let request = Request::post("/profile/ABC123/rollback/1")
+            .header(AUTHORIZATION, format!("Bearer {}", app.token(user)))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // everything needed is stored with the version, so the archive isn't downloaded
+        assert!(!app
+            .requests()
+            .iter()
+            .any(|request| request.starts_with("GET")));
+
+        let versions = sqlx::query!(
+            "SELECT v.version, v.name, v.storage_key, v.content_hash, v.size_bytes
+            FROM profile_versions v
+            JOIN profiles p ON p.id = v.profile_id
+            WHERE p.short_id = $1
+            ORDER BY v.version",
+            "ABC123"
+        )
+        .fetch_all(&app.state.db)
+        .await
+        .unwrap();
+
+        let [first, _, restored] = versions.as_slice() else {
+            panic!("expected three versions, got {}", versions.len());
+        };
+        assert_eq!(restored.version, 3);
+        assert_eq!(restored.name, "First");
+        assert_eq!(restored.storage_key, first.storage_key);
+        assert_eq!(restored.content_hash, first.content_hash);
+        assert_eq!(restored.size_bytes, first.size_bytes);
+
+        let profile = sqlx::query!(
+            "SELECT name, content_hash, size_bytes FROM profiles WHERE short_id = $1",
+            "ABC123"
+        )
+        .fetch_one(&app.state.db)
+        .await
+        .unwrap();
+
+        assert_eq!(profile.name, "First");
+        assert_eq!(profile.content_hash, first.content_hash);
+        assert_eq!(Some(profile.size_bytes), first.size_bytes);
+    }
+
+    #[test]
+    fn unpacked_size_limit_is_reported_in_mib() {
+        let manifest = br#"{ "profileName": "Test", "mods": [] }"#;
+        let bytes = zip(&[(JSON_MANIFEST, manifest), ("config.cfg", &[0; 2048])]);
+
+        let err = read_manifest(Cursor::new(bytes), 1024).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Invalid ZIP archive: uncompressed size exceeds the maximum of 1 KiB"
+        );
+    }
+
+    #[test]
+    fn update_requires_existing_profile() {
+        let err = upload_action(false, None, HASH, None).unwrap_err();
+        assert!(matches!(err, AppError::NotFound));
+
+        let err = upload_action(false, Some(existing(true)), HASH, None).unwrap_err();
+        assert!(matches!(err, AppError::NotFound));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, see docs/hosting.md"]
+    async fn update_of_missing_profile_responds_with_404(db: PgPool) {
+        let app = TestApp::new(db).await;
+        let manifest = br#"{ "profileName": "Test", "mods": [] }"#;
+
+        let request = Request::put("/profile/ABC123")
+            .header(
+                AUTHORIZATION,
+                format!("Bearer {}", app.token(testing::user())),
+            )
+            .body(Body::from(zip(&[(JSON_MANIFEST, manifest)])))
+            .unwrap();
+
+        let response = app.router().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(app.requests().is_empty(), "nothing should be uploaded");
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["message"], "Not found.");
+    }
+
+    #[test]
+    fn update_skips_unchanged_profile() {
+        let action = upload_action(false, Some(existing(false)), HASH, None).unwrap();
+        assert!(matches!(action, UploadAction::Unchanged(_)));
+
+        // repeating the current visibility isn't a change either
+        let action =
+            upload_action(false, Some(existing(false)), HASH, Some(Visibility::Public)).unwrap();
+        assert!(matches!(action, UploadAction::Unchanged(_)));
+    }
+
+    #[test]
+    fn update_replaces_changed_archive() {
+        let action = upload_action(false, Some(existing(false)), &[4, 5, 6], None).unwrap();
+        assert!(matches!(action, UploadAction::Update));
+    }
+
+    /// The name is part of the archive, so only the visibility can change without the hash.
+    #[test]
+    fn update_applies_changed_visibility() {
+        let action = upload_action(
+            false,
+            Some(existing(false)),
+            HASH,
+            Some(Visibility::Private),
+        )
+        .unwrap();
+        assert!(matches!(action, UploadAction::Update));
+    }
+}
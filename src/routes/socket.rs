@@ -1,16 +1,48 @@
 use axum::{
-    extract::{State, WebSocketUpgrade},
+    extract::{Query, State, WebSocketUpgrade},
     response::Response,
     routing::any,
     Router,
 };
+use http::{header::AUTHORIZATION, HeaderMap};
+use serde::Deserialize;
 
-use crate::prelude::*;
+use crate::{
+    auth::{self, token},
+    prelude::*,
+};
 
 pub fn routes() -> Router<AppState> {
     Router::new().route("/connect", any(connect))
 }
 
-async fn connect(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
-    ws.on_upgrade(move |socket| crate::socket::handle(socket, state))
+#[derive(Debug, Deserialize)]
+struct ConnectQuery {
+    /// Browsers can't set headers on WebSocket requests, so the token can also be passed here.
+    token: Option<String>,
+    /// Send large messages gzipped, see [`crate::socket::handle`].
+    #[serde(default)]
+    compress: bool,
+}
+
+/// Authentication is optional, but an invalid token is rejected with a regular
+/// error response before the connection is upgraded.
+async fn connect(
+    State(state): State<AppState>,
+    Query(query): Query<ConnectQuery>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> AppResult<Response> {
+    let token = match (&query.token, headers.contains_key(AUTHORIZATION)) {
+        (_, true) => Some(auth::bearer_token(&headers)?),
+        (Some(token), false) => Some(token.as_str()),
+        (None, false) => None,
+    };
+
+    let user = token
+        .map(|token| token::verify(token, &state))
+        .transpose()?
+        .map(|claims| auth::User::from(claims.user));
+
+    Ok(ws.on_upgrade(move |socket| crate::socket::handle(socket, user, query.compress, state)))
 }
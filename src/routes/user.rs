@@ -1,13 +1,30 @@
-use axum::{extract::State, routing::get, Json, Router};
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
 
-use crate::{auth::AuthUser, prelude::*, profile::ProfileId};
+use crate::{
+    auth::{self, AuthUser},
+    prelude::*,
+    profile::{ProfileId, Visibility},
+    routes::{Pagination, Path},
+    short_uuid::ShortUuid,
+    webhook,
+};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
-        //.route("/{name}", get(get_user))
         .route("/me", get(me))
+        .route("/me/usage", get(usage))
+        .route(
+            "/me/webhook",
+            get(get_webhook).put(set_webhook).delete(delete_webhook),
+        )
+        .route("/{name_or_id}", get(get_user))
 }
 
 #[derive(Debug, Serialize)]
@@ -17,67 +34,291 @@ struct User {
     name: String,
     display_name: String,
     avatar: Option<String>,
-    profiles: Option<Vec<UserProfile>>,
+    avatar_url: String,
+    public_flags: i32,
+    banner: Option<String>,
+    avatar_decoration: Option<String>,
+    public_id: ShortUuid,
+    /// Only shown to admins.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::rfc3339::option::serialize"
+    )]
+    last_login_at: Option<DateTime<Utc>>,
+    profiles: Vec<UserProfile>,
 }
 
-#[derive(Debug, Serialize, sqlx::Type)]
+#[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct UserProfile {
     #[serde(rename = "id")]
     short_id: ProfileId,
     name: String,
     community: Option<String>,
+    #[serde(with = "crate::rfc3339")]
     created_at: DateTime<Utc>,
+    #[serde(with = "crate::rfc3339")]
     updated_at: DateTime<Utc>,
+    visibility: Visibility,
+    downloads: i32,
+    /// Size of the profile's archive in bytes.
+    size_bytes: i64,
 }
 
-async fn me(AuthUser(user): AuthUser, State(state): State<AppState>) -> AppResult<Json<User>> {
-    query_user(user.name, &state).await.map(Json)
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ProfileSort {
+    /// Most recently updated first.
+    #[default]
+    Updated,
+    /// Most recently created first.
+    Created,
+    /// Alphabetically by name.
+    Name,
 }
 
-// this might get added later
+impl ProfileSort {
+    fn as_str(self) -> &'static str {
+        match self {
+            ProfileSort::Updated => "updated",
+            ProfileSort::Created => "created",
+            ProfileSort::Name => "name",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileFilter {
+    community: Option<String>,
+    #[serde(default)]
+    sort: ProfileSort,
+}
+
+async fn me(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<ProfileFilter>,
+) -> AppResult<Json<User>> {
+    let is_admin = user.is_admin;
+
+    query_user(user.name, pagination, filter, true, is_admin, &state)
+        .await
+        .map(Json)
+}
 
-/*
 async fn get_user(
-    Path(name): Path<String>,
+    Path(name_or_id): Path<String>,
     State(state): State<AppState>,
+    requester: Option<AuthUser>,
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<ProfileFilter>,
 ) -> AppResult<Json<User>> {
-    query_user(name, &state).await.map(Json)
+    let is_admin = requester.is_some_and(|AuthUser(user)| user.is_admin);
+
+    query_user(name_or_id, pagination, filter, false, is_admin, &state)
+        .await
+        .map(Json)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageResponse {
+    /// Total size of the user's profile archives in bytes.
+    used: u64,
+    /// `None` if the user has unlimited storage.
+    limit: Option<u64>,
+}
+
+async fn usage(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+) -> AppResult<Json<UsageResponse>> {
+    let used = sqlx::query_scalar!(
+        r#"SELECT COALESCE(SUM(size_bytes), 0)::BIGINT AS "used!"
+        FROM profiles
+        WHERE owner_id = $1"#,
+        user.id
+    )
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(Json(UsageResponse {
+        used: used as u64,
+        limit: state.profile_config.storage_quota,
+    }))
 }
-*/
 
-async fn query_user(name: String, state: &AppState) -> AppResult<User> {
-    let user = sqlx::query_as!(
-        User,
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookResponse {
+    url: String,
+    /// False if the webhook was disabled after failing too many times.
+    enabled: bool,
+    /// Failed deliveries in a row.
+    failures: i32,
+}
+
+async fn get_webhook(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+) -> AppResult<Json<WebhookResponse>> {
+    let webhook = sqlx::query_as!(
+        WebhookResponse,
         r#"SELECT
-            u.discord_id,
-            u.name,
-            u.display_name,
-            u.avatar,
-            COALESCE (
-                ARRAY_AGG ((
-                    p.short_id,
-                    p.name,
-                    p.community,
-                    p.created_at,
-                    p.updated_at
-                )) FILTER (WHERE p.id IS NOT NULL),
-                ARRAY[]::record[]
-            ) AS "profiles: Vec<UserProfile>"
-        FROM users u
-        LEFT JOIN profiles p
-            ON p.owner_id = u.id
-        WHERE u.name = $1
-        GROUP BY
-            u.discord_id,
-            u.name,
-            u.display_name,
-            u.avatar"#,
-        name
+            url,
+            disabled_at IS NULL AS "enabled!",
+            failures
+        FROM webhooks
+        WHERE user_id = $1"#,
+        user.id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    Ok(Json(webhook))
+}
+
+#[derive(Debug, Deserialize)]
+struct SetWebhookRequest {
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SetWebhookResponse {
+    url: String,
+    /// Key of the HMAC signatures, only shown once.
+    secret: String,
+}
+
+/// Registers or replaces the user's webhook. This always generates a new secret
+/// and re-enables the webhook if it was disabled.
+async fn set_webhook(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Json(req): Json<SetWebhookRequest>,
+) -> AppResult<Json<SetWebhookResponse>> {
+    let url = webhook::validate_url(&req.url)?;
+    let secret = webhook::generate_secret();
+
+    sqlx::query!(
+        "INSERT INTO webhooks (user_id, url, secret)
+        VALUES ($1, $2, $3)
+        ON CONFLICT(user_id)
+        DO UPDATE SET
+            url = EXCLUDED.url,
+            secret = EXCLUDED.secret,
+            failures = 0,
+            disabled_at = NULL",
+        user.id,
+        url.as_str(),
+        secret
+    )
+    .execute(&state.db)
+    .await?;
+
+    Ok(Json(SetWebhookResponse {
+        url: url.into(),
+        secret,
+    }))
+}
+
+async fn delete_webhook(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+) -> AppResult<StatusCode> {
+    let result = sqlx::query!("DELETE FROM webhooks WHERE user_id = $1", user.id)
+        .execute(&state.db)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Looks up a user by their public id or username, preferring the id if both match.
+///
+/// If `include_hidden` is false, only public profiles are returned.
+/// `include_admin_info` should only be set if the requester is an admin.
+async fn query_user(
+    name_or_id: String,
+    pagination: Pagination,
+    filter: ProfileFilter,
+    include_hidden: bool,
+    include_admin_info: bool,
+    state: &AppState,
+) -> AppResult<User> {
+    let user = sqlx::query!(
+        "SELECT
+            id,
+            discord_id,
+            name,
+            display_name,
+            avatar,
+            public_flags,
+            banner,
+            avatar_decoration,
+            public_id,
+            last_login_at
+        FROM users
+        WHERE name = $1 OR public_id = $2
+        ORDER BY public_id = $2 DESC
+        LIMIT 1",
+        name_or_id,
+        ShortUuid::try_from(name_or_id.clone()).ok().map(|id| id.0)
     )
     .fetch_optional(&state.db)
     .await?
     .ok_or(AppError::NotFound)?;
 
-    Ok(user)
+    let profiles = sqlx::query_as!(
+        UserProfile,
+        r#"SELECT
+            short_id AS "short_id: ProfileId",
+            name,
+            community,
+            created_at,
+            updated_at,
+            visibility AS "visibility: Visibility",
+            downloads,
+            size_bytes
+        FROM profiles
+        WHERE
+            owner_id = $1 AND
+            deleted_at IS NULL AND
+            ($4 OR visibility = 'public') AND
+            ($5::TEXT IS NULL OR community = $5)
+        ORDER BY
+            CASE WHEN $6 = 'name' THEN LOWER(name) END ASC,
+            CASE WHEN $6 = 'created' THEN created_at END DESC,
+            updated_at DESC
+        LIMIT $2
+        OFFSET $3"#,
+        user.id,
+        pagination.limit(),
+        pagination.offset(),
+        include_hidden,
+        filter.community,
+        filter.sort.as_str()
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(User {
+        avatar_url: auth::avatar_url(&user.discord_id, user.avatar.as_deref()),
+        discord_id: user.discord_id,
+        name: user.name,
+        display_name: user.display_name,
+        avatar: user.avatar,
+        public_flags: user.public_flags,
+        banner: user.banner,
+        avatar_decoration: user.avatar_decoration,
+        public_id: user.public_id.into(),
+        last_login_at: user.last_login_at.filter(|_| include_admin_info),
+        profiles,
+    })
 }
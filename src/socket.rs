@@ -2,59 +2,146 @@ use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
     hash::Hash,
+    io::Write,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use anyhow::bail;
 use axum::extract::ws::{self, WebSocket};
+use flate2::{write::GzEncoder, Compression};
 use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::{sync::mpsc, time::Instant};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::{
+    auth::User,
     profile::{self, ProfileId, ProfileMetadata},
     AppState, RedisConn,
 };
 
 const PROFILE_UPDATE: &str = "profile-update";
 const PROFILE_DELETE: &str = "profile-delete";
+const COLLABORATORS_CHANGED: &str = "collaborators-changed";
+const USER_PROFILE_UPDATE: &str = "user-profile-update";
+const USER_PROFILE_DELETE: &str = "user-profile-delete";
 
-type ListenerMap = HashMap<ProfileId, HashSet<Listener>>;
+/// Messages shorter than this aren't worth compressing.
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Something a connection can subscribe to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Topic {
+    Profile(ProfileId),
+    /// All profiles owned by the user with the given Discord id.
+    User(String),
+}
+
+type ListenerMap = HashMap<Topic, HashSet<Listener>>;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How often clients are pinged to check that they're still connected.
+    pub ping_interval: Duration,
+    /// How long to wait for a pong after a ping before dropping the connection.
+    pub pong_timeout: Duration,
+    /// The maximum number of profiles a single connection can subscribe to.
+    pub max_subscriptions: usize,
+    /// Updates to the same profile within this window are collapsed into one message
+    /// with the latest metadata. Zero sends every update immediately.
+    pub coalesce_window: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(10),
+            max_subscriptions: 100,
+            coalesce_window: Duration::ZERO,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct State {
     listeners: Arc<Mutex<ListenerMap>>,
+    config: Arc<Config>,
+    /// Used to publish notifications. Cloning it is cheap since it's multiplexed.
+    redis: RedisConn,
 }
 
 impl State {
-    pub fn new(redis: mpsc::UnboundedReceiver<redis::PushInfo>) -> Self {
+    /// `pushes` should receive the push messages of `redis`, which is used to publish
+    /// notifications and to reconnect after the connection drops.
+    pub fn new(
+        pushes: mpsc::UnboundedReceiver<redis::PushInfo>,
+        redis: RedisConn,
+        config: Config,
+    ) -> Self {
         let state = Self {
             listeners: Default::default(),
+            config: Arc::new(config),
+            redis,
         };
 
-        tokio::spawn(handle_redis(state.clone(), redis));
+        tokio::spawn(handle_redis(state.clone(), pushes));
 
         state
     }
 
-    pub fn notify_profile_updated(&self, redis: RedisConn, metadata: &ProfileMetadata) {
+    /// Announces a change to a profile, both to its own subscribers and to those of its owner.
+    /// This also invalidates its cached metadata.
+    pub async fn notify_profile_updated(&self, metadata: &ProfileMetadata) {
         self.notify_redis(
-            redis,
-            format!("{PROFILE_UPDATE}:{}", metadata.short_id),
+            vec![
+                format!("{PROFILE_UPDATE}:{}", metadata.short_id),
+                format!("{USER_PROFILE_UPDATE}:{}", metadata.owner.discord_id),
+            ],
+            profile::cache_key(&metadata.short_id),
             metadata,
         )
+        .await;
     }
 
-    pub fn notify_profile_deleted(&self, redis: RedisConn, id: &ProfileId) {
-        self.notify_redis(redis, format!("{PROFILE_DELETE}:{id}",), id)
+    /// Announces that a profile was deleted, both to its own subscribers and to those of its owner.
+    pub async fn notify_profile_deleted(&self, id: &ProfileId, owner_discord_id: &str) {
+        self.notify_redis(
+            vec![
+                format!("{PROFILE_DELETE}:{id}"),
+                format!("{USER_PROFILE_DELETE}:{owner_discord_id}"),
+            ],
+            profile::cache_key(id),
+            id,
+        )
+        .await
     }
 
-    fn notify_redis<T: Serialize>(&self, mut redis: RedisConn, channel: String, payload: &T) {
+    pub async fn notify_collaborators_changed(&self, id: &ProfileId, collaborators: &[User]) {
+        self.notify_redis(
+            vec![format!("{COLLABORATORS_CHANGED}:{id}")],
+            profile::cache_key(id),
+            &collaborators,
+        )
+        .await
+    }
+
+    /// Invalidates the cache key and publishes the payload to every channel in a single pipeline.
+    ///
+    /// This is awaited so the cache is cleared before the response is sent, otherwise a client
+    /// fetching the profile right after changing it could get the old version. Failures are
+    /// only logged since the change itself has already been saved.
+    async fn notify_redis<T: Serialize>(
+        &self,
+        channels: Vec<String>,
+        cache_key: String,
+        payload: &T,
+    ) {
         let json = match serde_json::to_string(payload) {
             Ok(str) => str,
             Err(err) => {
@@ -63,20 +150,85 @@ impl State {
             }
         };
 
-        tokio::spawn(async move {
-            let result = redis::cmd("PUBLISH")
-                .arg(&[channel, json])
-                .query_async::<()>(&mut redis)
-                .await;
+        let mut pipe = redis::pipe();
+        pipe.del(cache_key).ignore();
+
+        for channel in &channels {
+            pipe.publish(channel, &json).ignore();
+        }
+
+        if let Err(err) = pipe.query_async::<()>(&mut self.redis.clone()).await {
+            error!("failed to publish to {}: {err}", channels.join(", "));
+        }
+    }
+
+    fn subscribe(&self, topics: impl IntoIterator<Item = Topic>, listener: &Listener) {
+        Self::subscribe_local(&mut self.listeners.lock().unwrap(), topics, listener);
+    }
 
-            if let Err(err) = result {
-                error!("failed to publish redis message: {err}");
+    fn unsubscribe<'a>(&self, topics: impl IntoIterator<Item = &'a Topic>, listener: &Listener) {
+        Self::unsubscribe_local(&mut self.listeners.lock().unwrap(), topics, listener);
+    }
+
+    fn subscribe_local(
+        listeners: &mut ListenerMap,
+        topics: impl IntoIterator<Item = Topic>,
+        listener: &Listener,
+    ) {
+        for topic in topics {
+            listeners.entry(topic).or_default().insert(listener.clone());
+        }
+    }
+
+    /// Removes a listener from the given topics' sets, dropping sets that become empty.
+    fn unsubscribe_local<'a>(
+        listeners: &mut ListenerMap,
+        topics: impl IntoIterator<Item = &'a Topic>,
+        listener: &Listener,
+    ) {
+        for topic in topics {
+            if let Some(set) = listeners.get_mut(topic) {
+                set.remove(listener);
+
+                if set.is_empty() {
+                    listeners.remove(topic);
+                }
             }
+        }
+    }
+
+    fn retain_subscribed(&self, subscriptions: &mut HashSet<Topic>, listener: &Listener) {
+        Self::retain_subscribed_local(&self.listeners.lock().unwrap(), subscriptions, listener);
+    }
+
+    /// Drops the topics that `listener` was removed from by [`dispatch_event`],
+    /// which happens when a profile is deleted or made private.
+    fn retain_subscribed_local(
+        listeners: &ListenerMap,
+        subscriptions: &mut HashSet<Topic>,
+        listener: &Listener,
+    ) {
+        subscriptions.retain(|topic| {
+            listeners
+                .get(topic)
+                .is_some_and(|set| set.contains(listener))
         });
     }
 
-    fn notify_local(listeners: &mut ListenerMap, profile_id: &ProfileId, message: ServerMessage) {
-        if let Some(set) = listeners.get(profile_id) {
+    /// How many connections on this node are subscribed to the profile.
+    ///
+    /// Counts aren't aggregated across nodes, so with multiple instances this
+    /// only covers the clients connected to this one.
+    fn watcher_count(&self, profile_id: &ProfileId) -> usize {
+        let listeners = self.listeners.lock().unwrap();
+
+        listeners
+            .get(&Topic::Profile(profile_id.clone()))
+            .map_or(0, HashSet::len)
+    }
+
+    fn notify_local(listeners: &mut ListenerMap, topic: &Topic, message: ServerMessage) {
+        if let Some(set) = listeners.get(topic) {
             for listener in set {
                 if listener.tx.send(message.clone()).is_err() {
                     warn!(
@@ -120,11 +272,53 @@ impl Hash for Listener {
 
 #[derive(Debug, Serialize, Clone)]
 #[serde(tag = "event", content = "payload", rename_all = "camelCase")]
-enum ServerMessage {
-    ProfileUpdated { metadata: ProfileMetadata },
-    ProfileDeleted { id: ProfileId },
-    ProfileNotFound { id: ProfileId },
-    Error { message: Cow<'static, str> },
+pub(crate) enum ServerMessage {
+    ProfileUpdated {
+        metadata: Box<ProfileMetadata>,
+    },
+    ProfileDeleted {
+        id: ProfileId,
+    },
+    ProfileNotFound {
+        id: ProfileId,
+    },
+    Error {
+        message: Cow<'static, str>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    Subscribed {
+        profile_id: ProfileId,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    Unsubscribed {
+        profile_id: ProfileId,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    CollaboratorsChanged {
+        profile_id: ProfileId,
+        collaborators: Vec<User>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    SubscribedUser {
+        discord_id: String,
+    },
+
+    /// Acknowledges [`ClientMessage::SubscribeMany`].
+    SubscribedMany {
+        subscribed: Vec<ProfileId>,
+        /// Profiles that don't exist, are private or went over the subscription limit.
+        failed: Vec<ProfileId>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    WatcherCount {
+        profile_id: ProfileId,
+        count: usize,
+    },
 }
 
 #[derive(Debug, Deserialize)]
@@ -135,80 +329,181 @@ enum ClientMessage {
 
     #[serde(rename_all = "camelCase")]
     Unsubscribe { profile_id: ProfileId },
+
+    /// Subscribes to updates of all profiles owned by a user. Only allowed for your own id.
+    #[serde(rename_all = "camelCase")]
+    SubscribeUser { discord_id: String },
+
+    /// Like `Subscribe`, but for multiple profiles at once. Useful when reconnecting.
+    #[serde(rename_all = "camelCase")]
+    SubscribeMany { profile_ids: Vec<ProfileId> },
+
+    /// Asks how many clients are subscribed to a profile, see [`State::watcher_count`].
+    #[serde(rename_all = "camelCase")]
+    GetWatcherCount { profile_id: ProfileId },
 }
 
-pub(crate) async fn handle(socket: WebSocket, state: AppState) {
+/// `user` is the authenticated user, if the connection was opened with a token.
+///
+/// If `compress` is set, large messages are sent as gzipped binary messages instead of text.
+/// This stands in for `permessage-deflate`, which axum doesn't support.
+pub(crate) async fn handle(socket: WebSocket, user: Option<User>, compress: bool, state: AppState) {
     let (sender, receiver) = socket.split();
     let (tx, rx) = mpsc::unbounded_channel();
 
-    tokio::spawn(write(sender, rx));
-    tokio::spawn(read(receiver, Listener::new(tx), state));
+    state.metrics.socket_connections.inc();
+
+    tokio::spawn(write(sender, rx, state.sockets.config.clone(), compress));
+    tokio::spawn(read(receiver, Listener::new(tx), user, state));
 }
 
-async fn read(receiver: SplitStream<WebSocket>, listener: Listener, state: AppState) {
-    match read_inner(receiver, &listener, &state).await {
+async fn read(
+    receiver: SplitStream<WebSocket>,
+    listener: Listener,
+    user: Option<User>,
+    state: AppState,
+) {
+    let mut subscriptions = HashSet::new();
+
+    match read_inner(
+        receiver,
+        &listener,
+        user.as_ref(),
+        &mut subscriptions,
+        &state,
+    )
+    .await
+    {
         Ok(close_reason) => info!("stopping socket read task: {close_reason}"),
         Err(err) => warn!("error running socket read task, stopping: {err}"),
     };
 
-    let mut listeners = state.sockets.listeners.lock().unwrap();
+    // only touch the sets this connection actually joined
+    state.sockets.unsubscribe(&subscriptions, &listener);
 
-    for set in listeners.values_mut() {
-        set.remove(&listener);
-    }
+    state.metrics.socket_connections.dec();
 }
 
 async fn read_inner(
     mut receiver: SplitStream<WebSocket>,
     listener: &Listener,
+    user: Option<&User>,
+    subscriptions: &mut HashSet<Topic>,
     state: &AppState,
 ) -> anyhow::Result<&'static str> {
-    while let Some(item) = receiver.next().await {
-        let item = item?;
+    let config = &state.sockets.config;
+    let mut last_pong = Instant::now();
+
+    loop {
+        let deadline = last_pong + config.ping_interval + config.pong_timeout;
+
+        let item = match tokio::time::timeout_at(deadline, receiver.next()).await {
+            Ok(Some(item)) => item?,
+            Ok(None) => break,
+            Err(_) => return Ok("client stopped responding to pings"),
+        };
 
         let text = match item {
             ws::Message::Text(utf8_bytes) => utf8_bytes,
             ws::Message::Close(_) => {
                 return Ok("close message received");
             }
+            ws::Message::Pong(_) => {
+                last_pong = Instant::now();
+                continue;
+            }
+            // pings are answered automatically
+            ws::Message::Ping(_) => continue,
             other => {
                 warn!("received unexpected message: {other:?}");
                 continue;
             }
         };
 
-        let response = match serde_json::from_str::<ClientMessage>(text.as_ref()) {
-            Ok(ClientMessage::Subscribe { profile_id }) => {
-                if profile::exists(state, &profile_id).await? {
-                    let mut listeners = state.sockets.listeners.lock().unwrap();
+        // so that topics of deleted profiles don't count towards the limit
+        state.sockets.retain_subscribed(subscriptions, listener);
 
-                    listeners
-                        .entry(profile_id)
-                        .or_default()
-                        .insert(listener.clone());
+        let responses = match serde_json::from_str::<ClientMessage>(text.as_ref()) {
+            Ok(ClientMessage::Subscribe { profile_id }) => {
+                let topic = Topic::Profile(profile_id.clone());
 
-                    None
+                if let Err(err) = check_subscription_limit(subscriptions, &topic, config) {
+                    vec![err]
                 } else {
-                    Some(ServerMessage::ProfileNotFound { id: profile_id })
+                    // subscribe before fetching so that no update can slip in between
+                    state.sockets.subscribe([topic.clone()], listener);
+
+                    // updates are broadcast to every subscriber, so private profiles can't be subscribed to
+                    let metadata = match profile::get(state, &profile_id).await {
+                        Ok(metadata) => metadata.filter(|metadata| metadata.is_visible_to(None)),
+                        Err(err) => {
+                            // the topic isn't in `subscriptions` yet, so it wouldn't be cleaned up on disconnect
+                            state.sockets.unsubscribe([&topic], listener);
+                            return Err(err.into());
+                        }
+                    };
+
+                    match metadata {
+                        Some(metadata) => {
+                            subscriptions.insert(topic);
+
+                            vec![
+                                ServerMessage::Subscribed { profile_id },
+                                ServerMessage::ProfileUpdated {
+                                    metadata: Box::new(metadata),
+                                },
+                            ]
+                        }
+                        None => {
+                            state.sockets.unsubscribe([&topic], listener);
+
+                            vec![ServerMessage::ProfileNotFound { id: profile_id }]
+                        }
+                    }
                 }
             }
             Ok(ClientMessage::Unsubscribe { profile_id }) => {
-                let mut listeners = state.sockets.listeners.lock().unwrap();
+                let topic = Topic::Profile(profile_id.clone());
+
+                state.sockets.unsubscribe([&topic], listener);
+                subscriptions.remove(&topic);
+
+                vec![ServerMessage::Unsubscribed { profile_id }]
+            }
+            Ok(ClientMessage::SubscribeUser { discord_id }) => {
+                let topic = Topic::User(discord_id.clone());
+
+                if user.is_none_or(|user| user.discord_id != discord_id) {
+                    vec![ServerMessage::Error {
+                        message: "Can only subscribe to your own profiles.".into(),
+                    }]
+                } else if let Err(err) = check_subscription_limit(subscriptions, &topic, config) {
+                    vec![err]
+                } else {
+                    state.sockets.subscribe([topic.clone()], listener);
+                    subscriptions.insert(topic);
 
-                listeners.entry(profile_id).or_default().remove(listener);
+                    vec![ServerMessage::SubscribedUser { discord_id }]
+                }
+            }
+            Ok(ClientMessage::SubscribeMany { profile_ids }) => {
+                subscribe_many(profile_ids, listener, subscriptions, state).await?
+            }
+            Ok(ClientMessage::GetWatcherCount { profile_id }) => {
+                let count = state.sockets.watcher_count(&profile_id);
 
-                None
+                vec![ServerMessage::WatcherCount { profile_id, count }]
             }
             Err(err) => {
                 let response = ServerMessage::Error {
                     message: format!("Failed to deserialize message: {err}.").into(),
                 };
 
-                Some(response)
+                vec![response]
             }
         };
 
-        if let Some(response) = response {
+        for response in responses {
             if listener.tx.send(response).is_err() {
                 bail!("send channel closed");
             }
@@ -218,20 +513,165 @@ async fn read_inner(
     Ok("socket closed")
 }
 
+async fn subscribe_many(
+    profile_ids: Vec<ProfileId>,
+    listener: &Listener,
+    subscriptions: &mut HashSet<Topic>,
+    state: &AppState,
+) -> anyhow::Result<Vec<ServerMessage>> {
+    let mut available = state
+        .sockets
+        .config
+        .max_subscriptions
+        .saturating_sub(subscriptions.len());
+
+    let mut accepted = Vec::new();
+    let mut failed = Vec::new();
+    let mut seen = HashSet::new();
+
+    for profile_id in profile_ids {
+        if !seen.insert(profile_id.clone()) {
+            continue;
+        }
+
+        if subscriptions.contains(&Topic::Profile(profile_id.clone())) {
+            accepted.push(profile_id);
+        } else if available > 0 {
+            available -= 1;
+            accepted.push(profile_id);
+        } else {
+            failed.push(profile_id);
+        }
+    }
+
+    // subscribe before fetching so that no update can slip in between
+    state
+        .sockets
+        .subscribe(accepted.iter().cloned().map(Topic::Profile), listener);
+
+    let fetched = match profile::get_many(state, &accepted).await {
+        Ok(fetched) => fetched,
+        Err(err) => {
+            // new topics aren't in `subscriptions` yet, so they wouldn't be cleaned up on disconnect
+            let topics: Vec<_> = accepted.into_iter().map(Topic::Profile).collect();
+            state.sockets.unsubscribe(&topics, listener);
+            return Err(err.into());
+        }
+    };
+
+    let mut visible: HashMap<ProfileId, ProfileMetadata> = fetched
+        .into_iter()
+        .filter(|metadata| metadata.is_visible_to(None))
+        .map(|metadata| (metadata.short_id.clone(), metadata))
+        .collect();
+
+    let mut messages = Vec::new();
+    let mut subscribed = Vec::new();
+    let mut rejected = Vec::new();
+
+    for profile_id in accepted {
+        let topic = Topic::Profile(profile_id.clone());
+
+        match visible.remove(&profile_id) {
+            Some(metadata) => {
+                subscriptions.insert(topic);
+                subscribed.push(profile_id);
+                messages.push(ServerMessage::ProfileUpdated {
+                    metadata: Box::new(metadata),
+                });
+            }
+            None => {
+                subscriptions.remove(&topic);
+                rejected.push(topic);
+                failed.push(profile_id);
+            }
+        }
+    }
+
+    state.sockets.unsubscribe(&rejected, listener);
+
+    messages.insert(0, ServerMessage::SubscribedMany { subscribed, failed });
+
+    Ok(messages)
+}
+
+fn check_subscription_limit(
+    subscriptions: &HashSet<Topic>,
+    topic: &Topic,
+    config: &Config,
+) -> Result<(), ServerMessage> {
+    let max_subscriptions = config.max_subscriptions;
+
+    if !subscriptions.contains(topic) && subscriptions.len() >= max_subscriptions {
+        Err(ServerMessage::Error {
+            message: format!("Cannot subscribe to more than {max_subscriptions} profiles at once.")
+                .into(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
 async fn write(
     mut sender: SplitSink<WebSocket, ws::Message>,
     mut rx: mpsc::UnboundedReceiver<ServerMessage>,
+    config: Arc<Config>,
+    compress: bool,
 ) {
-    while let Some(msg) = rx.recv().await {
-        let msg = match serde_json::to_string(&msg) {
-            Ok(str) => ws::Message::Text(str.into()),
-            Err(err) => {
-                error!("stopping socket write task: failed to serialize socket message: {err}");
+    let mut ping_interval = tokio::time::interval(config.ping_interval);
+    // the first tick completes immediately
+    ping_interval.tick().await;
+
+    // updates held back until the coalescing window ends, keyed by profile
+    let mut pending: HashMap<ProfileId, Box<ProfileMetadata>> = HashMap::new();
+    let flush = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(flush);
+
+    loop {
+        let msg = tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = ping_interval.tick() => {
+                if let Err(err) = sender.send(ws::Message::Ping(Default::default())).await {
+                    warn!("stopping socket write task: failed to send ping: {err}");
+                    return;
+                }
+
+                continue;
+            }
+            _ = &mut flush, if !pending.is_empty() => {
+                if let Err(err) = flush_pending(&mut sender, &mut pending, compress).await {
+                    warn!("stopping socket write task: transmit error: {err}");
+                    return;
+                }
+
                 continue;
             }
         };
 
-        if let Err(err) = sender.send(msg).await {
+        let msg = match msg {
+            ServerMessage::ProfileUpdated { metadata } if !config.coalesce_window.is_zero() => {
+                if pending.is_empty() {
+                    flush
+                        .as_mut()
+                        .reset(Instant::now() + config.coalesce_window);
+                }
+
+                pending.insert(metadata.short_id.clone(), metadata);
+                continue;
+            }
+            msg => msg,
+        };
+
+        // send held back updates first so that messages arrive in order
+        let result = match flush_pending(&mut sender, &mut pending, compress).await {
+            Ok(()) => send_message(&mut sender, &msg, compress).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = result {
             warn!("stopping socket write task: transmit error: {err}");
             return;
         }
@@ -240,12 +680,97 @@ async fn write(
     debug!("stopping socket write task: channel was closed")
 }
 
-async fn handle_redis(state: State, mut redis: mpsc::UnboundedReceiver<redis::PushInfo>) {
-    while let Some(msg) = redis.recv().await {
+async fn flush_pending(
+    sender: &mut SplitSink<WebSocket, ws::Message>,
+    pending: &mut HashMap<ProfileId, Box<ProfileMetadata>>,
+    compress: bool,
+) -> Result<(), axum::Error> {
+    for (_, metadata) in pending.drain() {
+        send_message(
+            sender,
+            &ServerMessage::ProfileUpdated { metadata },
+            compress,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn send_message(
+    sender: &mut SplitSink<WebSocket, ws::Message>,
+    msg: &ServerMessage,
+    compress: bool,
+) -> Result<(), axum::Error> {
+    let json = match serde_json::to_string(msg) {
+        Ok(json) => json,
+        Err(err) => {
+            error!("failed to serialize socket message: {err}");
+            return Ok(());
+        }
+    };
+
+    let msg = if compress && json.len() >= COMPRESSION_THRESHOLD {
+        match gzip(json.as_bytes()) {
+            Ok(bytes) => ws::Message::Binary(bytes.into()),
+            Err(err) => {
+                warn!("failed to compress socket message: {err}");
+                ws::Message::Text(json.into())
+            }
+        }
+    } else {
+        ws::Message::Text(json.into())
+    };
+
+    sender.send(msg).await
+}
+
+fn gzip(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+async fn handle_redis(state: State, mut pushes: mpsc::UnboundedReceiver<redis::PushInfo>) {
+    while let Some(msg) = pushes.recv().await {
+        if msg.kind == redis::PushKind::Disconnection {
+            warn!("lost connection to redis, reconnecting");
+            tokio::spawn(reconnect_redis(state.redis.clone()));
+            continue;
+        }
+
         if let Err(err) = handle_redis_message(&state, msg).await {
             error!("failed to handle redis message: {err}");
         }
     }
+
+    error!("redis push channel closed, socket notifications are no longer received");
+}
+
+/// The connection manager reconnects and resubscribes by itself, but gives up after
+/// a few attempts until the next command is sent. Since the subscriptions don't send
+/// any commands, keep pinging with backoff until the connection is back.
+async fn reconnect_redis(mut redis: RedisConn) {
+    const MAX_DELAY: Duration = Duration::from_secs(30);
+
+    let mut delay = Duration::from_secs(1);
+    let mut attempts = 1;
+
+    loop {
+        match redis::cmd("PING").query_async::<()>(&mut redis).await {
+            Ok(()) => {
+                info!("reconnected to redis after {attempts} attempt(s)");
+                return;
+            }
+            Err(err) => {
+                warn!("failed to reconnect to redis (attempt {attempts}): {err}");
+            }
+        }
+
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_DELAY);
+        attempts += 1;
+    }
 }
 
 async fn handle_redis_message(state: &State, msg: redis::PushInfo) -> anyhow::Result<()> {
@@ -258,13 +783,12 @@ async fn handle_redis_message(state: &State, msg: redis::PushInfo) -> anyhow::Re
     // skip the pattern
     _ = values.next();
 
-    let (event_name, profile_id) = match values.next() {
+    let (event_name, id) = match values.next() {
         Some(redis::Value::BulkString(bytes)) => {
             let str = String::try_from(bytes)?;
-            let (event_name, profile_id) = str.split_once(":").expect("no colon in channel name");
-            let profile_id: ProfileId = profile_id.to_string().try_into()?;
+            let (event_name, id) = str.split_once(":").expect("no colon in channel name");
 
-            (event_name.to_string(), profile_id)
+            (event_name.to_string(), id.to_string())
         }
         value => bail!("expected channel name, got {value:?}"),
     };
@@ -274,31 +798,263 @@ async fn handle_redis_message(state: &State, msg: redis::PushInfo) -> anyhow::Re
         value => bail!("expected event payload, got {value:?}"),
     };
 
-    let mut listeners = state.listeners.lock().unwrap();
+    dispatch_event(
+        &mut state.listeners.lock().unwrap(),
+        &event_name,
+        id,
+        &payload,
+    )
+}
+
+/// Forwards an event published by [`State::notify_redis`] to the local listeners.
+fn dispatch_event(
+    listeners: &mut ListenerMap,
+    event_name: &str,
+    id: String,
+    payload: &str,
+) -> anyhow::Result<()> {
+    if event_name == USER_PROFILE_UPDATE {
+        // the owner is always allowed to see their own profiles, even private ones
+        let metadata: Box<ProfileMetadata> = serde_json::from_str(payload)?;
+
+        State::notify_local(
+            listeners,
+            &Topic::User(id),
+            ServerMessage::ProfileUpdated { metadata },
+        );
+
+        return Ok(());
+    }
+
+    if event_name == USER_PROFILE_DELETE {
+        let profile_id: ProfileId = serde_json::from_str(payload)?;
+
+        State::notify_local(
+            listeners,
+            &Topic::User(id),
+            ServerMessage::ProfileDeleted { id: profile_id },
+        );
+
+        return Ok(());
+    }
 
-    match event_name.as_str() {
+    let profile_id: ProfileId = id.try_into()?;
+    let topic = Topic::Profile(profile_id.clone());
+
+    match event_name {
         PROFILE_UPDATE => {
-            let metadata: ProfileMetadata = serde_json::from_str(&payload)?;
+            let metadata: Box<ProfileMetadata> = serde_json::from_str(payload)?;
 
-            State::notify_local(
-                &mut listeners,
-                &profile_id,
-                ServerMessage::ProfileUpdated { metadata },
-            );
+            if metadata.is_visible_to(None) {
+                State::notify_local(
+                    listeners,
+                    &topic,
+                    ServerMessage::ProfileUpdated { metadata },
+                );
+            } else {
+                // the profile was made private, so subscribers lose access to it
+                State::notify_local(
+                    listeners,
+                    &topic,
+                    ServerMessage::ProfileNotFound { id: profile_id },
+                );
+
+                listeners.remove(&topic);
+            }
         }
         PROFILE_DELETE => {
-            let id: ProfileId = serde_json::from_str(&payload)?;
+            let id: ProfileId = serde_json::from_str(payload)?;
+
+            State::notify_local(listeners, &topic, ServerMessage::ProfileDeleted { id });
+
+            listeners.remove(&topic);
+        }
+        COLLABORATORS_CHANGED => {
+            let collaborators: Vec<User> = serde_json::from_str(payload)?;
 
             State::notify_local(
-                &mut listeners,
-                &profile_id,
-                ServerMessage::ProfileDeleted { id },
+                listeners,
+                &topic,
+                ServerMessage::CollaboratorsChanged {
+                    profile_id,
+                    collaborators,
+                },
             );
-
-            listeners.remove(&profile_id);
         }
         name => bail!("unknown event: {name}"),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Display;
+
+    use super::*;
+    use crate::testing::TestApp;
+
+    fn listener() -> (Listener, mpsc::UnboundedReceiver<ServerMessage>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Listener::new(tx), rx)
+    }
+
+    fn topic(id: &str) -> Topic {
+        Topic::Profile(ProfileId::Short(id.to_owned()))
+    }
+
+    #[test]
+    fn unsubscribe_only_touches_own_topics() {
+        let mut listeners = ListenerMap::new();
+        let (a, _a_rx) = listener();
+        let (b, _b_rx) = listener();
+
+        let a_topics = HashSet::from([topic("AAA"), topic("SHARED")]);
+        State::subscribe_local(&mut listeners, a_topics.clone(), &a);
+        State::subscribe_local(&mut listeners, [topic("SHARED"), topic("BBB")], &b);
+
+        // what happens when `a` disconnects
+        State::unsubscribe_local(&mut listeners, &a_topics, &a);
+
+        assert!(!listeners.contains_key(&topic("AAA")));
+        assert_eq!(listeners[&topic("SHARED")], HashSet::from([b.clone()]));
+        assert_eq!(listeners[&topic("BBB")], HashSet::from([b]));
+    }
+
+    #[test]
+    fn deleted_profiles_free_up_subscriptions() {
+        let config = Config {
+            max_subscriptions: 1,
+            ..Default::default()
+        };
+
+        let mut listeners = ListenerMap::new();
+        let (listener, _rx) = listener();
+        let mut subscriptions = HashSet::from([topic("AAA111")]);
+        State::subscribe_local(&mut listeners, subscriptions.clone(), &listener);
+
+        assert!(check_subscription_limit(&subscriptions, &topic("BBB222"), &config).is_err());
+
+        let id = ProfileId::Short("AAA111".to_owned());
+        dispatch(&mut listeners, PROFILE_DELETE, &id, &id);
+        State::retain_subscribed_local(&listeners, &mut subscriptions, &listener);
+
+        assert!(subscriptions.is_empty());
+        assert!(check_subscription_limit(&subscriptions, &topic("BBB222"), &config).is_ok());
+    }
+
+    /// Sends `payload` through JSON like [`State::notify_redis`] does.
+    fn dispatch(
+        listeners: &mut ListenerMap,
+        event_name: &str,
+        id: impl Display,
+        payload: impl Serialize,
+    ) {
+        let payload = serde_json::to_string(&payload).unwrap();
+        dispatch_event(listeners, event_name, id.to_string(), &payload).unwrap();
+    }
+
+    /// Dispatches the messages that `app` published, as if they came back from Redis.
+    fn deliver(listeners: &mut ListenerMap, app: &TestApp) {
+        for (channel, payload) in app.published() {
+            let (event_name, id) = channel.split_once(':').unwrap();
+            dispatch_event(listeners, event_name, id.to_owned(), &payload).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn owner_subscribers_receive_profile_deletes() {
+        let app = TestApp::without_db().await;
+        let id = ProfileId::Short("ABC123".to_owned());
+        let owner = "123456789012345678";
+
+        let mut listeners = ListenerMap::new();
+        let (profile_listener, mut profile_rx) = listener();
+        let (user_listener, mut user_rx) = listener();
+        State::subscribe_local(
+            &mut listeners,
+            [Topic::Profile(id.clone())],
+            &profile_listener,
+        );
+        State::subscribe_local(
+            &mut listeners,
+            [Topic::User(owner.to_owned())],
+            &user_listener,
+        );
+
+        app.state.sockets.notify_profile_deleted(&id, owner).await;
+        deliver(&mut listeners, &app);
+
+        for rx in [&mut profile_rx, &mut user_rx] {
+            match rx.try_recv() {
+                Ok(ServerMessage::ProfileDeleted { id: deleted }) => assert_eq!(deleted, id),
+                other => panic!("expected ProfileDeleted, got {other:?}"),
+            }
+        }
+
+        // the owner stays subscribed to their other profiles
+        assert!(listeners.contains_key(&Topic::User(owner.to_owned())));
+    }
+
+    #[test]
+    fn subscribers_receive_profile_updates() {
+        let metadata = profile::tests::metadata();
+        let id = metadata.short_id.clone();
+        let owner = metadata.owner.discord_id.clone();
+
+        let mut listeners = ListenerMap::new();
+        let (profile_listener, mut profile_rx) = listener();
+        let (user_listener, mut user_rx) = listener();
+        State::subscribe_local(
+            &mut listeners,
+            [Topic::Profile(id.clone())],
+            &profile_listener,
+        );
+        State::subscribe_local(&mut listeners, [Topic::User(owner.clone())], &user_listener);
+
+        dispatch(&mut listeners, PROFILE_UPDATE, &id, &metadata);
+        dispatch(&mut listeners, USER_PROFILE_UPDATE, &owner, &metadata);
+
+        for rx in [&mut profile_rx, &mut user_rx] {
+            match rx.try_recv() {
+                Ok(ServerMessage::ProfileUpdated { metadata: received }) => {
+                    assert_eq!(received.short_id, id);
+                    assert_eq!(received.owner.public_id, metadata.owner.public_id);
+                }
+                other => panic!("expected ProfileUpdated, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn subscribers_receive_collaborator_changes() {
+        let metadata = profile::tests::metadata();
+        let id = metadata.short_id.clone();
+
+        let mut listeners = ListenerMap::new();
+        let (listener, mut rx) = listener();
+        State::subscribe_local(&mut listeners, [Topic::Profile(id.clone())], &listener);
+
+        dispatch(
+            &mut listeners,
+            COLLABORATORS_CHANGED,
+            &id,
+            &metadata.collaborators,
+        );
+
+        match rx.try_recv() {
+            Ok(ServerMessage::CollaboratorsChanged {
+                profile_id,
+                collaborators,
+            }) => {
+                assert_eq!(profile_id, id);
+                assert_eq!(collaborators.len(), 1);
+                assert_eq!(
+                    collaborators[0].public_id,
+                    metadata.collaborators[0].public_id
+                );
+            }
+            other => panic!("expected CollaboratorsChanged, got {other:?}"),
+        }
+    }
+}
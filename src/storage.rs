@@ -1,7 +1,18 @@
-use std::{fmt::Display, sync::Arc};
+use std::{fmt::Display, path::Path, sync::Arc, time::Duration};
 
+use anyhow::bail;
 use axum::body::Bytes;
-use http::Method;
+use http::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    Method, StatusCode,
+};
+use prometheus::Histogram;
+use rand::Rng;
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const BASE_RETRY_DELAY: Duration = Duration::from_millis(250);
 
 /// A client to interact with the Supabase storage API.
 ///
@@ -12,6 +23,8 @@ pub struct Client {
     api_key: Arc<str>,
     base_url: Arc<str>,
     http: reqwest::Client,
+    max_retries: u32,
+    latency: Option<Histogram>,
 }
 
 impl Client {
@@ -26,9 +39,23 @@ impl Client {
             api_key,
             base_url,
             http,
+            max_retries: DEFAULT_MAX_RETRIES,
+            latency: None,
         }
     }
 
+    /// Sets how many times a request is retried after a connection error or 5xx response.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Records the duration of each request, including retries, in the given histogram.
+    pub fn with_latency_histogram(mut self, histogram: Histogram) -> Self {
+        self.latency = Some(histogram);
+        self
+    }
+
     fn object_path(&self, key: impl Display) -> String {
         format!("/object/{}/{}", self.bucket_name, key)
     }
@@ -38,10 +65,52 @@ impl Client {
         self.http.request(method, url).bearer_auth(&*self.api_key)
     }
 
+    /// Sends a request, retrying transient failures with exponential backoff.
+    async fn send(&self, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let _timer = self.latency.as_ref().map(Histogram::start_timer);
+
+        let mut attempt = 0;
+
+        loop {
+            let Some(current) = request.try_clone() else {
+                // streaming bodies can't be retried
+                return request.send().await;
+            };
+
+            let result = current.send().await;
+
+            let should_retry = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(err) => err.is_connect() || err.is_timeout() || err.is_request(),
+            };
+
+            if !should_retry || attempt >= self.max_retries {
+                return result;
+            }
+
+            let delay = BASE_RETRY_DELAY * 2u32.pow(attempt);
+            let jitter = rand::rng().random_range(Duration::ZERO..delay);
+
+            match &result {
+                Ok(response) => warn!(
+                    "storage request failed with status {}, retrying in {:?}",
+                    response.status(),
+                    delay + jitter
+                ),
+                Err(err) => warn!(
+                    "storage request failed: {err}, retrying in {:?}",
+                    delay + jitter
+                ),
+            }
+
+            tokio::time::sleep(delay + jitter).await;
+            attempt += 1;
+        }
+    }
+
     pub(crate) async fn download(&self, key: impl Display) -> anyhow::Result<Bytes> {
         let bytes = self
-            .request(self.object_path(key), Method::GET)
-            .send()
+            .send(self.request(self.object_path(key), Method::GET))
             .await?
             .error_for_status()?
             .bytes()
@@ -49,6 +118,68 @@ impl Client {
         Ok(bytes)
     }
 
+    /// Uploads a file by streaming it from disk.
+    ///
+    /// Since the body can only be sent once, failed requests aren't retried.
+    pub(crate) async fn upload_file(
+        &self,
+        key: impl Display,
+        path: &Path,
+        content_type: &str,
+    ) -> anyhow::Result<()> {
+        let file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+
+        let request = self
+            .request(self.object_path(&key), Method::POST)
+            .header(CONTENT_LENGTH, size)
+            .body(reqwest::Body::wrap_stream(ReaderStream::new(file)));
+
+        self.send_upload(key, request, content_type).await
+    }
+
+    async fn send_upload(
+        &self,
+        key: impl Display,
+        request: reqwest::RequestBuilder,
+        content_type: &str,
+    ) -> anyhow::Result<()> {
+        let request = request
+            .header(CONTENT_TYPE, content_type)
+            .header("x-upsert", "true");
+
+        let response = self.send(request).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            bail!(
+                "failed to upload object to {} ({status}): {text}",
+                self.object_url(&key)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Deletes an object. Succeeds if the object doesn't exist.
+    pub(crate) async fn delete(&self, key: impl Display) -> anyhow::Result<()> {
+        let response = self
+            .send(self.request(self.object_path(&key), Method::DELETE))
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() && status != StatusCode::NOT_FOUND {
+            let text = response.text().await.unwrap_or_default();
+            bail!(
+                "failed to delete object at {} ({status}): {text}",
+                self.object_url(&key)
+            );
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn object_url(&self, key: impl Display) -> String {
         format!("{}{}", self.base_url, self.object_path(key))
     }
@@ -0,0 +1,270 @@
+//! Stand-ins for Redis, the storage API and Thunderstore, so routes can be
+//! tested end to end with nothing but (optionally) a database running.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Request, State},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use http::StatusCode;
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::{token, User},
+    metrics::Metrics,
+    prelude::*,
+    socket, storage, RedisConn,
+};
+
+const BUCKET: &str = "profiles";
+
+type Log<T> = Arc<Mutex<Vec<T>>>;
+
+/// An [`AppState`] whose services are fakes that record what they're sent.
+pub(crate) struct TestApp {
+    pub state: AppState,
+    redis: Log<Vec<String>>,
+    requests: Log<String>,
+    failing: Log<String>,
+}
+
+impl TestApp {
+    pub async fn new(db: PgPool) -> Self {
+        let redis = Log::default();
+        let requests = Log::default();
+        let failing = Log::default();
+
+        let redis_url = fake_redis(redis.clone()).await;
+        let http_url = fake_http(requests.clone(), failing.clone()).await;
+
+        let conn = RedisConn::new(redis::Client::open(redis_url).unwrap())
+            .await
+            .unwrap();
+        let http = reqwest::Client::new();
+
+        let state = AppState {
+            db,
+            storage: storage::Client::new(
+                BUCKET.into(),
+                "key".into(),
+                format!("{http_url}/storage/v1").into(),
+                http.clone(),
+            )
+            .with_max_retries(0),
+            http,
+            webhook_http: crate::webhook::client().unwrap(),
+            discord_client_id: "".into(),
+            discord_client_secret: "".into(),
+            discord_oauth_scopes: "".into(),
+            oauth_redirect_uri: "".into(),
+            jwt_key: Arc::new(token::JwtKey::hmac("secret").unwrap()),
+            require_whitelist: false,
+            discord_announce_webhook: None,
+            thunderstore_base_url: http_url.into(),
+            sockets: socket::State::new(
+                mpsc::unbounded_channel().1,
+                conn.clone(),
+                Default::default(),
+            ),
+            profile_config: Default::default(),
+            metrics: Arc::new(Metrics::new().unwrap()),
+            redis: conn,
+            migrations: Default::default(),
+        };
+
+        Self {
+            state,
+            redis,
+            requests,
+            failing,
+        }
+    }
+
+    /// An app whose database can't be reached, so every query fails.
+    pub async fn without_db() -> Self {
+        let db = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://localhost:1/gale")
+            .unwrap();
+
+        Self::new(db).await
+    }
+
+    /// Makes HTTP requests whose path starts with `prefix` fail with a 500.
+    pub fn fail_requests(&self, prefix: &str) {
+        self.failing.lock().unwrap().push(prefix.to_owned());
+    }
+
+    /// HTTP requests received so far, formatted like `POST /path`.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn router(&self) -> Router {
+        crate::routes(self.state.clone())
+    }
+
+    pub fn token(&self, user: User) -> String {
+        token::create(user.into(), &self.state).unwrap()
+    }
+
+    /// Messages published to Redis so far, as `(channel, payload)` pairs.
+    pub fn published(&self) -> Vec<(String, String)> {
+        self.redis
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|command| match command.as_slice() {
+                [name, channel, payload] if name.eq_ignore_ascii_case("PUBLISH") => {
+                    Some((channel.clone(), payload.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn user() -> User {
+    User {
+        id: 1,
+        discord_id: "123456789012345678".to_owned(),
+        name: "kesomannen".to_owned(),
+        display_name: "Kesomannen".to_owned(),
+        avatar: None,
+        public_flags: 0,
+        banner: None,
+        avatar_decoration: None,
+        public_id: Uuid::new_v4(),
+        is_admin: false,
+    }
+}
+
+/// Inserts a user like the one returned by [`user`], but with the id it gets in the database.
+pub(crate) async fn insert_user(db: &PgPool) -> User {
+    let mut user = user();
+
+    let row = sqlx::query!(
+        "INSERT INTO users (name, display_name, discord_id)
+        VALUES ($1, $2, $3)
+        RETURNING id, public_id",
+        user.name,
+        user.display_name,
+        user.discord_id
+    )
+    .fetch_one(db)
+    .await
+    .unwrap();
+
+    user.id = row.id;
+    user.public_id = row.public_id;
+    user
+}
+
+/// The path that the storage API serves the object at `key` from.
+pub(crate) fn object_path(key: &str) -> String {
+    format!("/storage/v1/object/{BUCKET}/{key}")
+}
+
+async fn fake_http(requests: Log<String>, failing: Log<String>) -> String {
+    async fn handle(
+        State((requests, failing)): State<(Log<String>, Log<String>)>,
+        request: Request,
+    ) -> Response {
+        let path = request.uri().path().to_owned();
+        requests
+            .lock()
+            .unwrap()
+            .push(format!("{} {path}", request.method()));
+
+        if failing
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+        {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+
+        if path.ends_with("/legacyprofile/create/") {
+            Json(serde_json::json!({ "key": Uuid::new_v4() })).into_response()
+        } else {
+            StatusCode::OK.into_response()
+        }
+    }
+
+    let app = Router::new()
+        .fallback(handle)
+        .with_state((requests, failing));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    url
+}
+
+/// Speaks just enough RESP to accept every command.
+async fn fake_redis(commands: Log<Vec<String>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("redis://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve_redis(stream, commands.clone()));
+        }
+    });
+
+    url
+}
+
+async fn serve_redis(stream: TcpStream, commands: Log<Vec<String>>) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if read.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+
+        let len: usize = line.trim_start_matches('*').trim_end().parse().unwrap_or(0);
+        let mut command = Vec::with_capacity(len);
+
+        for _ in 0..len {
+            line.clear();
+            read.read_line(&mut line).await?;
+            let size: usize = line.trim_start_matches('$').trim_end().parse().unwrap_or(0);
+
+            let mut arg = vec![0; size + 2];
+            read.read_exact(&mut arg).await?;
+            arg.truncate(size);
+            command.push(String::from_utf8_lossy(&arg).into_owned());
+        }
+
+        let name = command.first().map(|name| name.to_ascii_uppercase());
+        let reply: &[u8] = match name.as_deref() {
+            Some("GET") => b"$-1\r\n",
+            Some("INCR" | "DEL" | "EXPIRE" | "PUBLISH") => b":1\r\n",
+            _ => b"+OK\r\n",
+        };
+
+        if name.as_deref() != Some("CLIENT") {
+            commands.lock().unwrap().push(command);
+        }
+
+        write.write_all(reply).await?;
+    }
+}
@@ -0,0 +1,114 @@
+use std::{collections::HashMap, time::Duration};
+
+use futures_util::{stream, StreamExt};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::prelude::*;
+
+/// How long resolved packages are cached in Redis.
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// How many requests are sent to Thunderstore at once when resolving a profile.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// A summary of a Thunderstore package, used to render profiles before they're downloaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageInfo {
+    pub icon: String,
+    pub description: String,
+    pub latest_version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PackageResponse {
+    latest: PackageVersionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+struct PackageVersionResponse {
+    icon: String,
+    description: String,
+    version_number: String,
+}
+
+/// Looks up packages by their full name (`Owner-Name`).
+///
+/// Packages that can't be resolved are left out of the result.
+pub async fn resolve_packages(
+    state: &AppState,
+    full_names: Vec<String>,
+) -> HashMap<String, PackageInfo> {
+    stream::iter(full_names)
+        .map(|full_name| async move {
+            get_package(state, &full_name)
+                .await
+                .map(|info| (full_name, info))
+        })
+        .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+        .filter_map(|result| async move { result })
+        .collect()
+        .await
+}
+
+async fn get_package(state: &AppState, full_name: &str) -> Option<PackageInfo> {
+    let key = format!("thunderstore-package:{full_name}");
+    let mut redis = state.redis.clone();
+
+    match redis.get::<_, Option<String>>(&key).await {
+        // missing packages are cached as null too, so we don't keep asking for them
+        Ok(Some(json)) => return serde_json::from_str(&json).ok().flatten(),
+        Ok(None) => (),
+        Err(err) => warn!("failed to read cached package {full_name}: {err}"),
+    }
+
+    let info = match fetch_package(state, full_name).await {
+        Ok(info) => info,
+        Err(err) => {
+            // transient errors aren't cached
+            warn!("failed to resolve package {full_name}: {err:#}");
+            return None;
+        }
+    };
+
+    if let Ok(json) = serde_json::to_string(&info) {
+        if let Err(err) = redis
+            .set_ex::<_, _, ()>(&key, json, CACHE_TTL.as_secs())
+            .await
+        {
+            warn!("failed to cache package {full_name}: {err}");
+        }
+    }
+
+    info
+}
+
+/// Joins `path` onto the experimental API of [`AppState::thunderstore_base_url`].
+pub fn api_url(state: &AppState, path: &str) -> String {
+    format!("{}/api/experimental/{path}", state.thunderstore_base_url)
+}
+
+async fn fetch_package(state: &AppState, full_name: &str) -> anyhow::Result<Option<PackageInfo>> {
+    let Some((namespace, name)) = full_name.split_once('-') else {
+        return Ok(None);
+    };
+
+    let response = state
+        .http
+        .get(api_url(state, &format!("package/{namespace}/{name}/")))
+        .send()
+        .await?;
+
+    if response.status() == http::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let package: PackageResponse = response.error_for_status()?.json().await?;
+
+    Ok(Some(PackageInfo {
+        icon: package.latest.icon,
+        description: package.latest.description,
+        latest_version: package.latest.version_number,
+    }))
+}
@@ -0,0 +1,447 @@
+//! Notifies other servers of changes to profiles.
+//!
+//! Each user can register one webhook, which receives the same JSON messages as
+//! WebSocket clients. Requests are signed with an HMAC of the body, sent in the
+//! [`SIGNATURE_HEADER`] as `sha256=<hex>`.
+//!
+//! Deployments can also announce public profiles to a Discord channel, see [`announce_profile`].
+
+use std::{
+    fmt::Write,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use http::{header::CONTENT_TYPE, StatusCode};
+use rand::Rng;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use tracing::{error, info, warn};
+use url::{Host, Url};
+
+use crate::{
+    prelude::*,
+    profile::{ProfileId, ProfileMetadata},
+    socket::ServerMessage,
+};
+
+pub const SIGNATURE_HEADER: &str = "X-Gale-Signature";
+
+/// How many times a delivery is attempted before it counts as a failure.
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many deliveries in a row may fail before the webhook is disabled.
+const MAX_FAILURES: i32 = 5;
+const MAX_URL_LENGTH: usize = 2000;
+/// Longer rate limits than this aren't waited out.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Discord's blurple.
+const EMBED_COLOR: u32 = 0x5865f2;
+
+/// Checks that a webhook URL uses HTTPS and has a domain name.
+///
+/// The addresses the domain resolves to are checked on every delivery instead, see [`client`].
+pub fn validate_url(url: &str) -> AppResult<Url> {
+    if url.len() > MAX_URL_LENGTH {
+        return Err(AppError::bad_request(format!(
+            "Webhook URL must be at most {MAX_URL_LENGTH} characters."
+        )));
+    }
+
+    let url = Url::parse(url)
+        .map_err(|err| AppError::bad_request(format!("Invalid webhook URL: {err}.")))?;
+
+    if url.scheme() != "https" {
+        return Err(AppError::bad_request("Webhook URL must use HTTPS."));
+    }
+
+    match url.host() {
+        Some(Host::Domain(domain)) if domain != "localhost" => Ok(url),
+        _ => Err(AppError::bad_request(
+            "Webhook URL must have a domain name, not an IP address.",
+        )),
+    }
+}
+
+/// Builds the client that webhooks are delivered with.
+///
+/// It doesn't follow redirects and refuses to connect to hosts that resolve to a
+/// local or private address, so webhooks can't be used to reach internal services.
+pub fn client() -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .redirect(redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+}
+
+/// Uses the system resolver, but only returns public addresses.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(
+                    format!("{} doesn't resolve to a public address", name.as_str()).into(),
+                );
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    // 100.64.0.0/10, used for carrier-grade NAT
+    let shared = ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64;
+
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || shared)
+}
+
+/// Generates a new signing secret for a webhook.
+pub fn generate_secret() -> String {
+    let bytes: [u8; 32] = rand::rng().random();
+    BASE64_URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn notify_profile_updated(state: &AppState, metadata: &ProfileMetadata) {
+    let message = ServerMessage::ProfileUpdated {
+        metadata: Box::new(metadata.clone()),
+    };
+
+    tokio::spawn(deliver(state.clone(), metadata.owner.id, message));
+}
+
+pub fn notify_profile_deleted(state: &AppState, owner_id: i32, id: &ProfileId) {
+    let message = ServerMessage::ProfileDeleted { id: id.clone() };
+
+    tokio::spawn(deliver(state.clone(), owner_id, message));
+}
+
+/// Sends the message to the user's webhook, if they have one, retrying with
+/// exponential backoff. Runs in the background so requests don't wait on it.
+async fn deliver(state: AppState, user_id: i32, message: ServerMessage) {
+    let webhook = sqlx::query!(
+        "SELECT url, secret
+        FROM webhooks
+        WHERE user_id = $1 AND disabled_at IS NULL",
+        user_id
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    let webhook = match webhook {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => return,
+        Err(err) => {
+            error!("failed to look up webhook of user {user_id}: {err}");
+            return;
+        }
+    };
+
+    let body = match serde_json::to_vec(&message) {
+        Ok(body) => body,
+        Err(err) => {
+            error!("failed to serialize webhook payload: {err}");
+            return;
+        }
+    };
+
+    let signature = sign(&webhook.secret, &body);
+    let mut delay = INITIAL_RETRY_DELAY;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        match send(&state, &webhook.url, &body, &signature).await {
+            Ok(()) => {
+                record_success(&state, user_id).await;
+                return;
+            }
+            Err(err) => warn!(
+                "webhook delivery to user {user_id} failed (attempt {attempt}/{MAX_ATTEMPTS}): {err:#}"
+            ),
+        }
+
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    record_failure(&state, user_id).await;
+}
+
+async fn send(state: &AppState, url: &str, body: &[u8], signature: &str) -> anyhow::Result<()> {
+    let response = state
+        .webhook_http
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, format!("sha256={signature}"))
+        .body(body.to_vec())
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await
+        .context("failed to send request")?;
+
+    // redirects aren't followed, so they count as failures too
+    let status = response.status();
+    if !status.is_success() {
+        bail!("webhook responded with {status}");
+    }
+
+    Ok(())
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+async fn record_success(state: &AppState, user_id: i32) {
+    let result = sqlx::query!(
+        "UPDATE webhooks SET failures = 0 WHERE user_id = $1 AND failures > 0",
+        user_id
+    )
+    .execute(&state.db)
+    .await;
+
+    if let Err(err) = result {
+        error!("failed to reset webhook failures of user {user_id}: {err}");
+    }
+}
+
+async fn record_failure(state: &AppState, user_id: i32) {
+    let disabled = sqlx::query_scalar!(
+        r#"UPDATE webhooks
+        SET
+            failures = failures + 1,
+            disabled_at = CASE WHEN failures + 1 >= $2 THEN NOW() END
+        WHERE user_id = $1
+        RETURNING disabled_at IS NOT NULL AS "disabled!""#,
+        user_id,
+        MAX_FAILURES
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    match disabled {
+        Ok(Some(true)) => info!("disabled webhook of user {user_id} after {MAX_FAILURES} failures"),
+        Ok(_) => (),
+        Err(err) => error!("failed to record webhook failure of user {user_id}: {err}"),
+    }
+}
+
+/// Posts an embed about a public profile to [`AppState::discord_announce_webhook`], if it's set.
+///
+/// `created` should be true the first time the profile is uploaded.
+pub fn announce_profile(state: &AppState, metadata: &ProfileMetadata, created: bool) {
+    let Some(url) = state.discord_announce_webhook.clone() else {
+        return;
+    };
+
+    let title = if created {
+        "New profile"
+    } else {
+        "Profile updated"
+    };
+
+    let body = json!({
+        "embeds": [{
+            "title": format!("{title}: {}", metadata.manifest.profile_name),
+            "color": EMBED_COLOR,
+            "fields": [
+                {
+                    "name": "Community",
+                    "value": metadata.manifest.community.as_deref().unwrap_or("Unknown"),
+                    "inline": true,
+                },
+                {
+                    "name": "Mods",
+                    "value": metadata.manifest.mods.len().to_string(),
+                    "inline": true,
+                },
+                {
+                    "name": "Import in Gale",
+                    "value": format!("`{}`", crate::routes::desktop::deep_link(&metadata.short_id)),
+                },
+            ],
+            "author": {
+                "name": metadata.owner.display_name,
+                "icon_url": crate::auth::avatar_url(&metadata.owner.discord_id, metadata.owner.avatar.as_deref()),
+            },
+            "timestamp": metadata.updated_at.to_rfc3339(),
+        }]
+    });
+
+    let id = metadata.short_id.clone();
+    let state = state.clone();
+
+    tokio::spawn(async move {
+        if let Err(err) = post_to_discord(&state, &url, &body).await {
+            warn!("failed to announce profile {id} on discord: {err:#}");
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimited {
+    /// In seconds.
+    retry_after: f64,
+}
+
+/// Posts to a Discord webhook, waiting out rate limits.
+async fn post_to_discord(
+    state: &AppState,
+    url: &str,
+    body: &serde_json::Value,
+) -> anyhow::Result<()> {
+    for _ in 0..MAX_ATTEMPTS {
+        let res = state
+            .http
+            .post(url)
+            .json(body)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?;
+
+        if res.status() != StatusCode::TOO_MANY_REQUESTS {
+            res.error_for_status()?;
+            return Ok(());
+        }
+
+        let retry_after = res
+            .json::<RateLimited>()
+            .await
+            .map_or(INITIAL_RETRY_DELAY, |body| {
+                Duration::try_from_secs_f64(body.retry_after).unwrap_or(MAX_RETRY_AFTER)
+            });
+
+        if retry_after > MAX_RETRY_AFTER {
+            anyhow::bail!("rate limited for {}s", retry_after.as_secs());
+        }
+
+        tokio::time::sleep(retry_after).await;
+    }
+
+    anyhow::bail!("still rate limited after {MAX_ATTEMPTS} attempts")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{response::Redirect, routing::post, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_allowed() {
+        let local = [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ];
+
+        for ip in local {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} should be rejected");
+        }
+
+        for ip in ["1.1.1.1", "162.159.128.233", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} should be allowed");
+        }
+    }
+
+    /// Serves `app` on a local port, returning the port.
+    async fn serve(app: Router) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        port
+    }
+
+    #[tokio::test]
+    async fn hosts_resolving_to_local_addresses_are_refused() {
+        let port = serve(Router::new().route("/", post(|| async {}))).await;
+
+        let err = client()
+            .unwrap()
+            .post(format!("http://localhost:{port}/"))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(err.is_connect(), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn redirects_are_not_followed() {
+        let app = Router::new().route(
+            "/",
+            post(|| async { Redirect::temporary("http://169.254.169.254/") }),
+        );
+        let port = serve(app).await;
+
+        // IP literals skip the resolver, which makes a local server reachable here
+        let response = client()
+            .unwrap()
+            .post(format!("http://127.0.0.1:{port}/"))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    }
+}